@@ -0,0 +1,26 @@
+// Filenames as raw bytes, so that names which are not valid UTF-8 can still be matched and
+// rewritten. Unix filenames are arbitrary bytes; elsewhere only valid UTF-8 names are supported.
+
+use std::ffi::{OsStr, OsString};
+
+#[cfg(unix)]
+pub fn from_os_str(s: &OsStr) -> Option<&[u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(s.as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn from_os_str(s: &OsStr) -> Option<&[u8]> {
+    s.to_str().map(str::as_bytes)
+}
+
+#[cfg(unix)]
+pub fn to_os_string(bytes: Vec<u8>) -> Option<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+pub fn to_os_string(bytes: Vec<u8>) -> Option<OsString> {
+    String::from_utf8(bytes).ok().map(OsString::from)
+}
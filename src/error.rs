@@ -0,0 +1,114 @@
+use std::{error, fmt, io, result};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use RenameOp;
+
+pub type Result<T> = result::Result<T, Error>;
+
+/// Everything that can stop a directory from being renumbered.
+#[derive(Debug)]
+pub enum Error {
+    /// The renumbering makes no sense, for example because its ranges overlap.
+    InvalidRenumbering(String),
+    /// Reading the directory, renaming a file or accessing the journal failed.
+    Io { path: PathBuf, source: io::Error },
+    /// The journal exists but could not be understood.
+    MalformedJournal { path: PathBuf, line: String },
+    /// Some files could not be renumbered, so nothing was.
+    BadFiles(Vec<FileError>),
+    /// More than one file would be renamed to the same name.
+    Collisions(Vec<Collision>),
+    /// Renames that would replace existing files which are not part of the plan.
+    Conflicts(Vec<RenameOp>),
+    /// The directory holds an interrupted run, which has to be resumed or rolled back first.
+    Interrupted(PathBuf),
+    /// There is no journal to undo or resume.
+    NoJournal(PathBuf),
+}
+
+/// A file that could not be renumbered.
+#[derive(Debug)]
+pub struct FileError {
+    pub path: PathBuf,
+    pub kind: FileErrorKind,
+}
+
+#[derive(Debug)]
+pub enum FileErrorKind {
+    /// The number in the name is too large to work with.
+    NumberTooLarge(String),
+    /// The new number is too large to work with.
+    Overflow(i64),
+    /// The renumbering has no new number for it, such as an expression that divides by zero.
+    NoResult(i64),
+    /// The new number is below the lowest number allowed.
+    BelowFloor { number: i64, floor: i64 },
+    /// It would be moved to the trash, but this path is in the way and is not a directory.
+    TrashNotDirectory(PathBuf),
+    /// The template gives it a name that is not a single filename, such as `..` or one with a `/`.
+    InvalidName(OsString),
+}
+
+/// Files that would all be renamed to the same name.
+#[derive(Debug)]
+pub struct Collision {
+    pub target: PathBuf,
+    pub sources: Vec<PathBuf>,
+}
+
+impl Error {
+    pub(crate) fn io(path: PathBuf, source: io::Error) -> Error {
+        Error::Io { path, source }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidRenumbering(ref reason) => write!(f, "invalid renumbering: {}", reason),
+            Error::Io { ref path, ref source } => write!(f, "{}: {}", path.display(), source),
+            Error::MalformedJournal { ref path, ref line } => write!(f, "{}: malformed journal line {:?}", path.display(), line),
+            Error::BadFiles(ref errors) => write!(f, "{} file(s) could not be renumbered", errors.len()),
+            Error::Collisions(ref collisions) => write!(f, "{} name(s) would be given to more than one file", collisions.len()),
+            Error::Conflicts(ref conflicts) => write!(f, "{} rename(s) would overwrite existing files", conflicts.len()),
+            Error::Interrupted(ref path) => write!(f, "{} contains an interrupted run, which has to be resumed or rolled back first", path.display()),
+            Error::NoJournal(ref path) => write!(f, "no journal found in {}", path.display()),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io { ref source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.kind)
+    }
+}
+
+impl fmt::Display for FileErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileErrorKind::NumberTooLarge(ref number) => write!(f, "number {} is too large", number),
+            FileErrorKind::Overflow(number) => write!(f, "renumbering {} would overflow", number),
+            FileErrorKind::NoResult(number) => write!(f, "the expression has no result for {}", number),
+            FileErrorKind::BelowFloor { number, floor } => write!(f, "renumbering would give it the number {}, which is below {}", number, floor),
+            FileErrorKind::TrashNotDirectory(ref trash) => write!(f, "it cannot be moved to the trash, because {} is not a directory", trash.display()),
+            FileErrorKind::InvalidName(ref name) => write!(f, "the template would give it the name {:?}, which is not a valid filename", name),
+        }
+    }
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sources: Vec<String> = self.sources.iter().map(|path| path.display().to_string()).collect();
+        write!(f, "{} would all be renamed to {}", sources.join(", "), self.target.display())
+    }
+}
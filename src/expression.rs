@@ -0,0 +1,395 @@
+// A small arithmetic language for working out new numbers from old ones, such as
+// `n < 100 ? n + 5 : n * 2`. Comparisons and logic work like in C: false is 0, and anything
+// else is true.
+
+use std::{fmt, str::FromStr};
+
+/// An arithmetic expression of the old number `n`.
+#[derive(Clone, Debug)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+/// Why an expression has no result for a number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvaluationError {
+    /// The result, or a step on the way to it, does not fit.
+    Overflow,
+    DivisionByZero,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Number(i64),
+    Variable,
+    Negate(Box<Node>),
+    Not(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+    Conditional(Box<Node>, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Variable,
+    Operator(Operator),
+    Not,
+    Question,
+    Colon,
+    Open,
+    Close,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        };
+        let root = parser.conditional()?;
+        match parser.peek() {
+            None => Ok(Expression { source: source.to_string(), root }),
+            Some(token) => Err(format!("unexpected {} after the end of the expression", token)),
+        }
+    }
+
+    /// Works out the new number for `n`.
+    pub fn evaluate(&self, n: i64) -> Result<i64, EvaluationError> {
+        self.root.evaluate(n)
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Expression, String> {
+        Expression::parse(source)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Node {
+    fn evaluate(&self, n: i64) -> Result<i64, EvaluationError> {
+        let overflow = |result: Option<i64>| result.ok_or(EvaluationError::Overflow);
+        match *self {
+            Node::Number(value) => Ok(value),
+            Node::Variable => Ok(n),
+            Node::Negate(ref operand) => overflow(operand.evaluate(n)?.checked_neg()),
+            Node::Not(ref operand) => Ok(i64::from(operand.evaluate(n)? == 0)),
+            Node::Conditional(ref condition, ref then, ref otherwise) => {
+                if condition.evaluate(n)? != 0 {
+                    then.evaluate(n)
+                } else {
+                    otherwise.evaluate(n)
+                }
+            }
+            // these only evaluate their right side when they need to
+            Node::Binary(Operator::And, ref left, ref right) => {
+                Ok(i64::from(left.evaluate(n)? != 0 && right.evaluate(n)? != 0))
+            }
+            Node::Binary(Operator::Or, ref left, ref right) => {
+                Ok(i64::from(left.evaluate(n)? != 0 || right.evaluate(n)? != 0))
+            }
+            Node::Binary(operator, ref left, ref right) => {
+                let left = left.evaluate(n)?;
+                let right = right.evaluate(n)?;
+                match operator {
+                    Operator::Add => overflow(left.checked_add(right)),
+                    Operator::Subtract => overflow(left.checked_sub(right)),
+                    Operator::Multiply => overflow(left.checked_mul(right)),
+                    Operator::Divide | Operator::Remainder if right == 0 => Err(EvaluationError::DivisionByZero),
+                    Operator::Divide => overflow(left.checked_div(right)),
+                    Operator::Remainder => overflow(left.checked_rem(right)),
+                    Operator::Less => Ok(i64::from(left < right)),
+                    Operator::LessOrEqual => Ok(i64::from(left <= right)),
+                    Operator::Greater => Ok(i64::from(left > right)),
+                    Operator::GreaterOrEqual => Ok(i64::from(left >= right)),
+                    Operator::Equal => Ok(i64::from(left == right)),
+                    Operator::NotEqual => Ok(i64::from(left != right)),
+                    Operator::And | Operator::Or => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            ' ' | '\t' => continue,
+            '0'..='9' => {
+                let mut digits = c.to_string();
+                while let Some(&digit) = chars.peek() {
+                    if !digit.is_ascii_digit() {
+                        break;
+                    }
+                    digits.push(digit);
+                    chars.next();
+                }
+                match digits.parse() {
+                    Ok(value) => Token::Number(value),
+                    Err(_) => return Err(format!("number {} is too large", digits)),
+                }
+            }
+            'n' => Token::Variable,
+            '+' => Token::Operator(Operator::Add),
+            '-' => Token::Operator(Operator::Subtract),
+            '*' => Token::Operator(Operator::Multiply),
+            '/' => Token::Operator(Operator::Divide),
+            '%' => Token::Operator(Operator::Remainder),
+            '?' => Token::Question,
+            ':' => Token::Colon,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '<' | '>' | '=' | '!' | '&' | '|' => {
+                let followed_by = |chars: &mut ::std::iter::Peekable<::std::str::Chars>, next: char| {
+                    if chars.peek() == Some(&next) {
+                        chars.next();
+                        true
+                    } else {
+                        false
+                    }
+                };
+                match c {
+                    '<' if followed_by(&mut chars, '=') => Token::Operator(Operator::LessOrEqual),
+                    '<' => Token::Operator(Operator::Less),
+                    '>' if followed_by(&mut chars, '=') => Token::Operator(Operator::GreaterOrEqual),
+                    '>' => Token::Operator(Operator::Greater),
+                    '=' if followed_by(&mut chars, '=') => Token::Operator(Operator::Equal),
+                    '!' if followed_by(&mut chars, '=') => Token::Operator(Operator::NotEqual),
+                    '!' => Token::Not,
+                    '&' if followed_by(&mut chars, '&') => Token::Operator(Operator::And),
+                    '|' if followed_by(&mut chars, '|') => Token::Operator(Operator::Or),
+                    _ => return Err(format!("unexpected {:?}", c)),
+                }
+            }
+            _ => return Err(format!("unexpected {:?}", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// how deep expressions can nest, so that parsing and evaluating them cannot run out of stack
+const MAX_DEPTH: usize = 256;
+
+// a recursive descent parser, with one method per precedence level from loosest to tightest
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    // how deep the node being parsed is in the tree, counted generously
+    depth: usize,
+}
+
+impl Parser {
+    fn descend(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(String::from("expression is nested too deeply"));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(ref token) if *token == expected => Ok(()),
+            Some(token) => Err(format!("expected {} but found {}", expected, token)),
+            None => Err(format!("expected {} but the expression ended", expected)),
+        }
+    }
+
+    fn conditional(&mut self) -> Result<Node, String> {
+        self.descend()?;
+        let condition = self.binary(0)?;
+        let node = if self.peek() == Some(&Token::Question) {
+            self.next();
+            let then = self.conditional()?;
+            self.expect(Token::Colon)?;
+            let otherwise = self.conditional()?;
+            Node::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise))
+        } else {
+            condition
+        };
+        self.depth -= 1;
+        Ok(node)
+    }
+
+    // parses operators that bind at least as tightly as the given precedence
+    fn binary(&mut self, precedence: u8) -> Result<Node, String> {
+        let depth = self.depth;
+        let mut left = self.unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some(&Token::Operator(operator)) if operator.precedence() >= precedence => operator,
+                _ => {
+                    self.depth = depth;
+                    return Ok(left);
+                }
+            };
+            self.next();
+            // every operator puts what came before it one level further down
+            self.descend()?;
+            let right = self.binary(operator.precedence() + 1)?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        self.descend()?;
+        let node = match self.next() {
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Variable) => Ok(Node::Variable),
+            Some(Token::Operator(Operator::Subtract)) => Ok(Node::Negate(Box::new(self.unary()?))),
+            Some(Token::Not) => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let inner = self.conditional()?;
+                self.expect(Token::Close)?;
+                Ok(inner)
+            }
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err(String::from("the expression ended unexpectedly")),
+        };
+        self.depth -= 1;
+        node
+    }
+}
+
+impl Operator {
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::Or => 0,
+            Operator::And => 1,
+            Operator::Equal | Operator::NotEqual => 2,
+            Operator::Less | Operator::LessOrEqual | Operator::Greater | Operator::GreaterOrEqual => 3,
+            Operator::Add | Operator::Subtract => 4,
+            Operator::Multiply | Operator::Divide | Operator::Remainder => 5,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Remainder => "%",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Variable => write!(f, "\"n\""),
+            Token::Operator(operator) => write!(f, "\"{}\"", operator.symbol()),
+            Token::Not => write!(f, "\"!\""),
+            Token::Question => write!(f, "\"?\""),
+            Token::Colon => write!(f, "\":\""),
+            Token::Open => write!(f, "\"(\""),
+            Token::Close => write!(f, "\")\""),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EvaluationError, Expression};
+
+    fn evaluate(source: &str, n: i64) -> Result<i64, EvaluationError> {
+        Expression::parse(source).unwrap().evaluate(n)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("1 + n * 2", 3), Ok(7));
+        assert_eq!(evaluate("(1 + n) * 2", 3), Ok(8));
+        assert_eq!(evaluate("n - 2 - 1", 10), Ok(7));
+        assert_eq!(evaluate("-n + 1", 3), Ok(-2));
+        assert_eq!(evaluate("(n - 1) / 2 + 1", 5), Ok(3));
+        assert_eq!(evaluate("n % 3 == 1 && n > 3 || n == 0", 4), Ok(1));
+    }
+
+    #[test]
+    fn conditional() {
+        let expression = Expression::parse("n < 100 ? n + 5 : n * 2").unwrap();
+        assert_eq!(expression.evaluate(99), Ok(104));
+        assert_eq!(expression.evaluate(100), Ok(200));
+        assert_eq!(evaluate("n < 10 ? 1 : n < 20 ? 2 : 3", 15), Ok(2));
+        assert_eq!(evaluate("n ? n : 0 ? 1 : 2", 0), Ok(2));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(evaluate("n / 0", 1), Err(EvaluationError::DivisionByZero));
+        assert_eq!(evaluate("n % (n - 1)", 1), Err(EvaluationError::DivisionByZero));
+        assert_eq!(evaluate("n == 1 ? 0 : 10 / (n - 1)", 1), Ok(0));
+        assert_eq!(evaluate("n * 9223372036854775807", 2), Err(EvaluationError::Overflow));
+    }
+
+    #[test]
+    fn malformed() {
+        for source in &["", "n +", "(n", "n)", "n ? 1", "m", "1 = 2", "99999999999999999999"] {
+            assert!(Expression::parse(source).is_err(), "{:?} parsed", source);
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        assert_eq!(evaluate(&format!("{}n{}", "(".repeat(50), ")".repeat(50)), 3), Ok(3));
+        assert_eq!(evaluate(&format!("{}n", "-".repeat(50)), 3), Ok(3));
+        for source in &[
+            format!("{}n{}", "(".repeat(50_000), ")".repeat(50_000)),
+            format!("{}n", "-".repeat(100_000)),
+            format!("{}n", "!".repeat(100_000)),
+            format!("n{}", " + 1".repeat(100_000)),
+            format!("n{}", " ? 1 : n".repeat(100_000)),
+        ] {
+            assert_eq!(Expression::parse(source).unwrap_err(), "expression is nested too deeply");
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// How new numbers are written out, described by a spec in the style of Rust's format strings,
+// such as `{n:>4}`, `{n:04}` or `{n:#x}`.
+
+use std::str::FromStr;
+
+/// How a new number is written in a name.
+///
+/// Without a width, numbers are zero-padded to the width chosen by the [`Padding`](::Padding),
+/// which counts digits only. An explicit width counts the sign and radix prefix too, as in Rust.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NumberFormat {
+    /// Pads to the width with this character on the given side, instead of with zeros after the
+    /// sign.
+    pub fill: Option<(char, Align)>,
+    /// Writes `0x`, `0o` or `0b` in front of numbers in those radixes.
+    pub alternate: bool,
+    pub width: Option<u32>,
+    pub radix: Radix,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Radix {
+    Decimal,
+    LowerHex,
+    UpperHex,
+    Octal,
+    Binary,
+}
+
+impl Default for NumberFormat {
+    fn default() -> NumberFormat {
+        NumberFormat {
+            fill: None,
+            alternate: false,
+            width: None,
+            radix: Radix::Decimal,
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Parses a spec such as `{n:>4}`, or just the part after the colon.
+    pub fn parse(source: &str) -> Result<NumberFormat, String> {
+        let spec = match source.strip_prefix("{n").and_then(|rest| rest.strip_suffix('}')) {
+            Some(rest) if rest.is_empty() => rest,
+            Some(rest) => rest.strip_prefix(':').ok_or_else(|| format!("{:?} is not of the form {{n:SPEC}}", source))?,
+            None => source,
+        };
+        let mut format = NumberFormat::default();
+        let mut chars: Vec<char> = spec.chars().collect();
+
+        // a fill character is only there if an alignment follows it
+        let align = |c: char| match c {
+            '<' => Some(Align::Left),
+            '^' => Some(Align::Center),
+            '>' => Some(Align::Right),
+            _ => None,
+        };
+        if let Some(align) = chars.get(1).cloned().and_then(align) {
+            // the fill ends up in filenames, so it cannot be anything that separates or ends a path,
+            // nor a digit that would be read back as part of the number
+            if let '/' | '\\' | '\0' | '0'..='9' = chars[0] {
+                return Err(format!("{:?} cannot be used as a fill character", chars[0]));
+            }
+            format.fill = Some((chars[0], align));
+            chars.drain(..2);
+        } else if let Some(align) = chars.first().cloned().and_then(align) {
+            format.fill = Some((' ', align));
+            chars.remove(0);
+        }
+        if chars.first() == Some(&'#') {
+            format.alternate = true;
+            chars.remove(0);
+        }
+        let zero = chars.first() == Some(&'0');
+        if zero {
+            chars.remove(0);
+        }
+        let digits: String = chars.iter().take_while(|c| c.is_ascii_digit()).collect();
+        if !digits.is_empty() {
+            format.width = Some(digits.parse().map_err(|_| format!("width {} is too large", digits))?);
+            chars.drain(..digits.len());
+            // as in Rust, a width on its own pads with spaces, and a leading zero with zeros
+            if !zero && format.fill.is_none() {
+                format.fill = Some((' ', Align::Right));
+            }
+        }
+        format.radix = match chars.iter().collect::<String>().as_str() {
+            "" | "d" => Radix::Decimal,
+            "x" => Radix::LowerHex,
+            "X" => Radix::UpperHex,
+            "o" => Radix::Octal,
+            "b" => Radix::Binary,
+            rest => return Err(format!("unexpected {:?} in number format {:?}", rest, source)),
+        };
+        Ok(format)
+    }
+
+    /// How many digits `n` takes up, leaving out any sign or prefix.
+    pub fn digit_count(&self, n: i64) -> u32 {
+        self.digits(n).len() as u32
+    }
+
+    /// Writes out `n`, zero-padded to `digits` digits unless the format has a width of its own.
+    pub fn format(&self, n: i64, digits: u32) -> String {
+        let sign = if n < 0 { "-" } else { "" };
+        let prefix = if self.alternate { self.radix.prefix() } else { "" };
+        let number = self.digits(n);
+        let len = sign.len() + prefix.len() + number.len();
+        match (self.fill, self.width) {
+            (Some((fill, align)), Some(width)) => {
+                let pad = (width as usize).saturating_sub(len);
+                let (before, after) = match align {
+                    Align::Left => (0, pad),
+                    Align::Center => (pad / 2, pad - pad / 2),
+                    Align::Right => (pad, 0),
+                };
+                let fill = fill.to_string();
+                format!("{}{}{}{}{}", fill.repeat(before), sign, prefix, number, fill.repeat(after))
+            }
+            (_, width) => {
+                let zeros = match width {
+                    Some(width) => (width as usize).saturating_sub(len),
+                    None => (digits as usize).saturating_sub(number.len()),
+                };
+                format!("{}{}{}{}", sign, prefix, "0".repeat(zeros), number)
+            }
+        }
+    }
+
+    /// Reads back a number written in this format's radix, with or without its radix prefix.
+    /// Returns `None` if it is not a number in that radix or is too large.
+    pub fn read(&self, text: &str) -> Option<i64> {
+        if !self.is_number(text) {
+            return None;
+        }
+        let (negative, digits) = self.split_number(text);
+        let sign = if negative { "-" } else { "" };
+        i64::from_str_radix(&format!("{}{}", sign, digits), self.radix.base()).ok()
+    }
+
+    // whether the text is a number in this format's radix, however large
+    pub(crate) fn is_number(&self, text: &str) -> bool {
+        let digits = self.split_number(text).1;
+        !digits.is_empty() && digits.chars().all(|c| c.is_digit(self.radix.base()))
+    }
+
+    // splits a number into whether it is negative and its digits, leaving out any radix prefix
+    pub(crate) fn split_number<'a>(&self, text: &'a str) -> (bool, &'a str) {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        match self.radix.prefix() {
+            "" => (negative, text),
+            prefix => (negative, text.strip_prefix(prefix).unwrap_or(text)),
+        }
+    }
+
+    fn digits(&self, n: i64) -> String {
+        let n = n.unsigned_abs();
+        match self.radix {
+            Radix::Decimal => n.to_string(),
+            Radix::LowerHex => format!("{:x}", n),
+            Radix::UpperHex => format!("{:X}", n),
+            Radix::Octal => format!("{:o}", n),
+            Radix::Binary => format!("{:b}", n),
+        }
+    }
+}
+
+impl Radix {
+    fn base(self) -> u32 {
+        match self {
+            Radix::Decimal => 10,
+            Radix::LowerHex | Radix::UpperHex => 16,
+            Radix::Octal => 8,
+            Radix::Binary => 2,
+        }
+    }
+
+    // what the alternate form writes in front of numbers in this radix
+    fn prefix(self) -> &'static str {
+        match self {
+            Radix::Decimal => "",
+            Radix::LowerHex | Radix::UpperHex => "0x",
+            Radix::Octal => "0o",
+            Radix::Binary => "0b",
+        }
+    }
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<NumberFormat, String> {
+        NumberFormat::parse(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Align, NumberFormat, Radix};
+
+    fn format(spec: &str, n: i64) -> String {
+        NumberFormat::parse(spec).unwrap().format(n, 3)
+    }
+
+    #[test]
+    fn parses_specs() {
+        assert_eq!(NumberFormat::parse("{n}").unwrap(), NumberFormat::default());
+        assert_eq!(NumberFormat::parse("{n:_^6}").unwrap(), NumberFormat::parse("_^6").unwrap());
+        let spec = NumberFormat::parse("*<#8X").unwrap();
+        assert_eq!(spec.fill, Some(('*', Align::Left)));
+        assert!(spec.alternate);
+        assert_eq!(spec.width, Some(8));
+        assert_eq!(spec.radix, Radix::UpperHex);
+    }
+
+    #[test]
+    fn formats_numbers() {
+        assert_eq!(format("", 7), "007");
+        assert_eq!(format("", 1234), "1234");
+        assert_eq!(format(">4", 7), "   7");
+        assert_eq!(format("4", 7), "   7");
+        assert_eq!(format("04", 7), "0007");
+        assert_eq!(format("04", -7), "-007");
+        assert_eq!(format("", -7), "-007");
+        assert_eq!(format("_^6", 42), "__42__");
+        assert_eq!(format("<4", 7), "7   ");
+        assert_eq!(format("#x", 31), "0x01f");
+        assert_eq!(format("#06x", 31), "0x001f");
+        assert_eq!(format("b", 5), "101");
+        assert_eq!(format("o", 8), "010");
+    }
+
+    #[test]
+    fn reads_numbers_back() {
+        for spec in &["", "04", "#x", "X", "#o", "#b", "_^9"] {
+            let format = NumberFormat::parse(spec).unwrap();
+            for &n in &[0, 7, -42, 255, i64::MIN, i64::MAX] {
+                let text = format.format(n, 3);
+                assert_eq!(format.read(text.trim_matches('_')), Some(n), "{:?} read back wrong with {:?}", text, spec);
+            }
+        }
+        let hex = NumberFormat::parse("#x").unwrap();
+        assert_eq!(hex.read("1f"), Some(31));
+        assert_eq!(hex.read("0x"), None);
+        assert_eq!(hex.read("1g"), None);
+        assert_eq!(NumberFormat::default().read("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        for spec in &["q", "{n:q}", "{nq}", "4.2", "99999999999", "/>4", "\\<4", "\0^4", "0>4", "7<4"] {
+            assert!(NumberFormat::parse(spec).is_err(), "{:?} was accepted", spec);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+// The journal is a record of every rename made by the last real run, kept in the directory that
+// run operated on. It starts with one `rename` line per step as tab-separated absolute paths, in
+// the order the steps will be performed, including any hops through temporary names. A `done`
+// line is appended as each step completes, so a run that was killed partway can be picked back up.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use bytes;
+use error::{Error, Result};
+use RenameOp;
+
+pub const JOURNAL_FILENAME: &str = ".father-file-numberer.journal";
+
+const HEADER: &str = "# father-file-numberer journal";
+const RENAME: &str = "rename";
+const DONE: &str = "done";
+
+pub fn journal_path(directory: &Path) -> PathBuf {
+    directory.join(JOURNAL_FILENAME)
+}
+
+// an open journal that completed steps get recorded in
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    // replaces any previous journal with the given renames, making sure it has hit the disk
+    pub fn create(directory: &Path, steps: &[RenameOp]) -> Result<Journal> {
+        let path = journal_path(directory);
+        let mut contents = String::new();
+        contents.push_str(HEADER);
+        contents.push('\n');
+        for step in steps {
+            let line = escape(&step.from).and_then(|from| Ok(format!("{}\t{}\t{}\n", RENAME, from, escape(&step.to)?)));
+            contents.push_str(&line.map_err(|e| Error::io(path.clone(), e))?);
+        }
+
+        let file = File::create(&path).and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+            Ok(file)
+        });
+        match file {
+            Ok(file) => Ok(Journal { path, file }),
+            Err(e) => Err(Error::io(path, e)),
+        }
+    }
+
+    // reopens an existing journal to record the rest of its steps
+    pub fn append(directory: &Path) -> Result<Journal> {
+        let path = journal_path(directory);
+        match OpenOptions::new().append(true).open(&path) {
+            Ok(file) => Ok(Journal { path, file }),
+            Err(e) => Err(Error::io(path, e)),
+        }
+    }
+
+    // removes the journal of a run that did not get to rename anything
+    pub fn discard(self) -> Result<()> {
+        let Journal { path, file } = self;
+        drop(file);
+        fs::remove_file(&path).map_err(|e| Error::io(path, e))
+    }
+
+    // records that the next step has been performed
+    pub fn mark_done(&mut self) -> Result<()> {
+        self.file.write_all(format!("{}\n", DONE).as_bytes())
+            .and_then(|()| self.file.sync_data())
+            .map_err(|e| Error::io(self.path.clone(), e))
+    }
+}
+
+// what a journal on disk says about its run
+pub struct JournalContents {
+    pub steps: Vec<RenameOp>,
+    // how many of the steps were recorded as done; steps always complete in order
+    pub completed: usize,
+}
+
+impl JournalContents {
+    pub fn is_complete(&self) -> bool {
+        self.completed >= self.steps.len()
+    }
+
+    /* A run can be killed between renaming a file and recording it, so the journal may be one
+     * step behind. If the next step's source is gone and its target is there, it already ran.
+     * Returns whether that was the case.
+     */
+    pub fn reconcile(&mut self) -> bool {
+        if let Some(step) = self.steps.get(self.completed) {
+            if step.from.symlink_metadata().is_err() && step.to.symlink_metadata().is_ok() {
+                self.completed += 1;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// reads the journal in a directory, if there is one
+pub fn read(directory: &Path) -> Result<Option<JournalContents>> {
+    let path = journal_path(directory);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::io(path, e)),
+    };
+    let mut contents = JournalContents {
+        steps: Vec::new(),
+        completed: 0,
+    };
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| Error::io(path.clone(), e))?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let step = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(RENAME), Some(from), Some(to), None) => match (unescape(from), unescape(to)) {
+                (Some(from), Some(to)) => Some(RenameOp { from, to }),
+                _ => return Err(Error::MalformedJournal { path, line }),
+            },
+            (Some(DONE), None, None, None) => None,
+            _ => return Err(Error::MalformedJournal { path, line }),
+        };
+        match step {
+            Some(step) => contents.steps.push(step),
+            None => contents.completed += 1,
+        }
+    }
+    Ok(Some(contents))
+}
+
+// collapses the renames into one rename per file, from where it started to where it ended up
+pub fn net_renames(steps: &[RenameOp]) -> Vec<RenameOp> {
+    // where each file currently is, and where it was before the first step
+    let mut origins: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut order: Vec<PathBuf> = Vec::new();
+    for step in steps {
+        let origin = match origins.remove(&step.from) {
+            Some(origin) => origin,
+            None => {
+                order.push(step.from.clone());
+                step.from.clone()
+            }
+        };
+        origins.insert(step.to.clone(), origin);
+    }
+
+    let mut destinations: HashMap<PathBuf, PathBuf> = origins.into_iter()
+        .map(|(current, origin)| (origin, current))
+        .collect();
+    order.into_iter()
+        .filter_map(|origin| {
+            let current = destinations.remove(&origin)?;
+            if current != origin {
+                Some(RenameOp { from: origin, to: current })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// bytes that are not valid UTF-8 are written as \xNN, so the journal itself stays UTF-8
+fn escape(path: &Path) -> io::Result<String> {
+    let mut remaining = bytes::from_os_str(path.as_os_str()).ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} cannot be recorded", path.display())))?;
+    let mut escaped = String::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let (valid, invalid) = match str::from_utf8(remaining) {
+            Ok(valid) => (valid, &[][..]),
+            Err(e) => {
+                let (valid, rest) = remaining.split_at(e.valid_up_to());
+                let invalid_len = e.error_len().unwrap_or(rest.len());
+                (str::from_utf8(valid).unwrap(), &rest[..invalid_len])
+            }
+        };
+        escaped.push_str(&valid.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n"));
+        for byte in invalid {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+        remaining = &remaining[valid.len() + invalid.len()..];
+    }
+    Ok(escaped)
+}
+
+fn unescape(field: &str) -> Option<PathBuf> {
+    let mut path = Vec::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('\\') => path.push(b'\\'),
+                Some('t') => path.push(b'\t'),
+                Some('n') => path.push(b'\n'),
+                Some('x') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    if hex.len() != 2 {
+                        return None;
+                    }
+                    path.push(u8::from_str_radix(&hex, 16).ok()?);
+                }
+                _ => return None,
+            }
+        } else {
+            let mut utf8 = [0; 4];
+            path.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+        }
+    }
+    bytes::to_os_string(path).map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{escape, net_renames, unescape};
+    use {bytes, RenameOp};
+
+    fn path(name: &[u8]) -> PathBuf {
+        PathBuf::from(bytes::to_os_string(name.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn escaping_round_trips() {
+        for name in &[&b"/tmp/plain 1.txt"[..], b"/tmp/tab\there\\and\nnewline", b"/tmp/x\\x41 literal", "/tmp/caf\u{e9} 2".as_bytes()] {
+            let escaped = escape(&path(name)).unwrap();
+            assert!(!escaped.contains('\t') && !escaped.contains('\n'));
+            assert_eq!(unescape(&escaped), Some(path(name)));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn escaping_round_trips_non_utf8() {
+        let name = b"/tmp/caf\xe9 1\xff\xfe.txt";
+        let escaped = escape(&path(name)).unwrap();
+        assert_eq!(escaped, "/tmp/caf\\xe9 1\\xff\\xfe.txt");
+        assert_eq!(unescape(&escaped), Some(path(name)));
+    }
+
+    #[test]
+    fn malformed_escapes_are_rejected() {
+        for field in &["trailing\\", "\\q", "\\x4", "\\xzz"] {
+            assert_eq!(unescape(field), None, "{:?} was accepted", field);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn net_renames_of_non_utf8_names() {
+        let op = |from: &[u8], to: &[u8]| RenameOp { from: path(from), to: path(to) };
+        // a swap through a temporary name, followed by an ordinary rename
+        let steps = vec![
+            op(b"a\xe9 1", b".a\xe9 1.ffn-tmp"),
+            op(b"a\xe9 2", b"a\xe9 1"),
+            op(b".a\xe9 1.ffn-tmp", b"a\xe9 2"),
+            op(b"b\xff 3", b"b\xff 4"),
+        ];
+        let escaped: Vec<RenameOp> = steps.iter()
+            .map(|step| RenameOp { from: unescape(&escape(&step.from).unwrap()).unwrap(), to: unescape(&escape(&step.to).unwrap()).unwrap() })
+            .collect();
+        assert_eq!(escaped, steps);
+        assert_eq!(net_renames(&escaped), vec![
+            op(b"a\xe9 1", b"a\xe9 2"),
+            op(b"a\xe9 2", b"a\xe9 1"),
+            op(b"b\xff 3", b"b\xff 4"),
+        ]);
+    }
+}
@@ -0,0 +1,343 @@
+//! Renumbers files by adjusting the number in each of their names.
+//!
+//! Renames are worked out up front by [`plan`], which orders them so that no file is ever
+//! renamed onto another, and then carried out by [`apply`]. Every applied plan is recorded in
+//! a [journal](journal) in the directory it ran in, so that it can be undone or, if it was
+//! interrupted, resumed.
+
+#[macro_use]
+extern crate lazy_static;
+extern crate regex;
+
+mod bytes;
+mod error;
+mod expression;
+mod format;
+pub mod journal;
+mod planner;
+mod renumbering;
+mod template;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::bytes::Regex;
+
+pub use error::{Collision, Error, FileError, FileErrorKind, Result};
+pub use expression::{EvaluationError, Expression};
+pub use format::{Align, NumberFormat, Radix};
+use journal::{Journal, JournalContents};
+pub use renumbering::{RangeOffset, Renumbering};
+pub use template::Template;
+use planner::Scan;
+
+/// The name of the group in [`RenumberOptions::pattern`] that holds the number.
+pub const NUMBER_GROUP: &str = "n";
+
+/// Settings controlling which files get renumbered, and how.
+#[derive(Clone, Debug)]
+pub struct RenumberOptions {
+    /// How matched files get their new numbers.
+    pub renumbering: Renumbering,
+    /// Also renumber files in subdirectories.
+    pub recursive: bool,
+    /// Which of the numbers in a name gets renumbered, when there is more than one.
+    pub which: WhichNumber,
+    /// Also look for numbers in the extension, as in `track.mp3`, rather than only before it.
+    pub include_extension: bool,
+    /// Treat a dash right before a number as its sign, as in `page-3`, unless it follows another
+    /// number, as in `2019-07-25`.
+    pub signed: bool,
+    /// Picks out the number in a name as the group named `n` of this pattern instead, leaving
+    /// names it does not match alone. Overrides `which`.
+    pub pattern: Option<Regex>,
+    /// Leave files with numbers lower than this alone.
+    pub start: Option<i64>,
+    /// Leave files with numbers higher than this alone.
+    pub end: Option<i64>,
+    /// How new numbers are padded with zeros.
+    pub padding: Padding,
+    /// How new numbers are written. Numbers are also looked for in its radix, and the fill it pads
+    /// them with is not counted as part of the name around them.
+    pub format: NumberFormat,
+    /// Builds new names from this instead of just swapping the number out.
+    pub template: Option<Template>,
+    /// The lowest new number allowed, if any.
+    pub floor: Option<i64>,
+    /// What to do about files whose new number would be below the floor.
+    pub below_floor: FloorPolicy,
+    /// What to do when a file would be renamed onto an existing file.
+    pub on_conflict: ConflictPolicy,
+}
+
+impl RenumberOptions {
+    pub fn new(renumbering: Renumbering) -> RenumberOptions {
+        RenumberOptions {
+            renumbering,
+            recursive: false,
+            which: WhichNumber::First,
+            include_extension: false,
+            signed: false,
+            pattern: None,
+            start: None,
+            end: None,
+            padding: Padding::Original,
+            format: NumberFormat::default(),
+            template: None,
+            floor: Some(0),
+            below_floor: FloorPolicy::Error,
+            on_conflict: ConflictPolicy::Abort,
+        }
+    }
+}
+
+/// Which run of digits in a name is its number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhichNumber {
+    First,
+    Last,
+    /// The nth run of digits, counting from 1. Names with fewer are left alone.
+    Nth(usize),
+}
+
+/// How wide new numbers are made by padding them with zeros.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Padding {
+    /// As wide as the number they replace if it was zero-padded, so `page007` becomes `page008`
+    /// while `page10` becomes `page9`.
+    Original,
+    /// At least this many digits wide.
+    Width(u32),
+    /// As wide as the widest new number in the same directory, so that they all line up.
+    Uniform,
+}
+
+/// What to do about files whose new number would be below the floor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloorPolicy {
+    /// Refuse to rename anything.
+    Error,
+    /// Give them the floor as their number instead.
+    Clamp,
+    /// Leave them alone.
+    Skip,
+}
+
+/// What to do about renames onto files that are not part of the plan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Refuse to rename anything.
+    Abort,
+    /// Leave the file that would have been renamed where it is.
+    Skip,
+    /// Replace the existing file.
+    Overwrite,
+}
+
+/// A single rename of one path to another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenameOp {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// A file that was left out of a plan.
+#[derive(Clone, Debug)]
+pub struct Skipped {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+#[derive(Clone, Debug)]
+pub enum SkipReason {
+    /// The name has no number in it.
+    NotMatching,
+    /// The name is not valid UTF-8, which is only supported on Unix.
+    NonUtf8Name,
+    /// The number is outside of the start and end bounds.
+    OutOfRange,
+    /// The file was parked under a temporary name by an earlier run.
+    Temporary,
+    /// The directory holds files deleted by an earlier run.
+    Trash,
+    /// The new number would have been below the floor.
+    BelowFloor(i64),
+    /// Renaming the file would have replaced the given existing file.
+    Conflict(PathBuf),
+}
+
+/// Everything needed to renumber a directory, worked out before any file is touched.
+#[derive(Debug)]
+pub struct RenamePlan {
+    /// The directory the plan is for, which is where its journal is kept.
+    pub directory: PathBuf,
+    /// Renames in the order they must be performed, including hops through temporary names.
+    pub steps: Vec<RenameOp>,
+    /// Existing files the plan will replace, when overwriting was allowed.
+    pub overwrites: Vec<PathBuf>,
+    pub skipped: Vec<Skipped>,
+    // number of steps already performed by an interrupted run that this plan finishes, but that
+    // its journal does not know about yet
+    resumes: Option<usize>,
+    // whether the plan deals with an interrupted run, whose journal has to be settled even when
+    // there is nothing left to rename
+    finishes_run: bool,
+}
+
+impl RenamePlan {
+    fn new(directory: &Path, ops: Vec<RenameOp>, skipped: Vec<Skipped>, on_conflict: ConflictPolicy) -> Result<RenamePlan> {
+        let mut plan = RenamePlan {
+            directory: directory.to_path_buf(),
+            steps: Vec::new(),
+            overwrites: Vec::new(),
+            skipped,
+            resumes: None,
+            finishes_run: false,
+        };
+        let mut ops = ops;
+
+        // nothing has been renamed yet, so this is the last chance to back out cleanly
+        let mut conflicts = planner::find_conflicts(&ops);
+        match on_conflict {
+            ConflictPolicy::Abort => {
+                if !conflicts.is_empty() {
+                    return Err(Error::Conflicts(conflicts.into_iter().map(|i| ops[i].clone()).collect()));
+                }
+            }
+            ConflictPolicy::Skip => {
+                // leaving a file in place can put it in the way of another rename, so repeat until settled
+                while !conflicts.is_empty() {
+                    for &i in conflicts.iter().rev() {
+                        let op = ops.remove(i);
+                        plan.skipped.push(Skipped { path: op.from, reason: SkipReason::Conflict(op.to) });
+                    }
+                    conflicts = planner::find_conflicts(&ops);
+                }
+            }
+            ConflictPolicy::Overwrite => {
+                plan.overwrites = conflicts.into_iter().map(|i| ops[i].to.clone()).collect();
+            }
+        }
+
+        plan.steps = planner::order_renames(ops)?;
+        Ok(plan)
+    }
+}
+
+/// Works out how to renumber the files in a directory without touching any of them.
+///
+/// Fails if the directory holds an interrupted run, which has to be resumed or rolled back first,
+/// or if any file that should be renumbered cannot be.
+pub fn plan(directory: &Path, options: &RenumberOptions) -> Result<RenamePlan> {
+    options.renumbering.validate().map_err(Error::InvalidRenumbering)?;
+    check_not_interrupted(directory)?;
+
+    let mut scan = Scan::default();
+    planner::plan_directory(directory, options, &mut scan)?;
+    if !scan.errors.is_empty() {
+        return Err(Error::BadFiles(scan.errors));
+    }
+    if !scan.collisions.is_empty() {
+        return Err(Error::Collisions(scan.collisions));
+    }
+    RenamePlan::new(directory, scan.ops, scan.skipped, options.on_conflict)
+}
+
+/// Works out how to revert the last run in a directory, according to its journal.
+pub fn plan_undo(directory: &Path, on_conflict: ConflictPolicy) -> Result<RenamePlan> {
+    let previous_run = check_not_interrupted(directory)?
+        .ok_or_else(|| Error::NoJournal(directory.to_path_buf()))?;
+    let ops = reversed(journal::net_renames(&previous_run.steps));
+    RenamePlan::new(directory, ops, Vec::new(), on_conflict)
+}
+
+/// Works out how to deal with an interrupted run in a directory, either by finishing it or by
+/// reverting the renames it already made. Returns `None` if there is no interrupted run.
+pub fn plan_resume(directory: &Path, rollback: bool, on_conflict: ConflictPolicy) -> Result<Option<RenamePlan>> {
+    let mut previous_run = match journal::read(directory)? {
+        Some(ref contents) if contents.is_complete() => return Ok(None),
+        Some(contents) => contents,
+        None => return Ok(None),
+    };
+    let caught_up = previous_run.reconcile();
+
+    if rollback {
+        let ops = reversed(journal::net_renames(&previous_run.steps[..previous_run.completed]));
+        let mut plan = RenamePlan::new(directory, ops, Vec::new(), on_conflict)?;
+        plan.finishes_run = true;
+        Ok(Some(plan))
+    } else {
+        Ok(Some(RenamePlan {
+            directory: directory.to_path_buf(),
+            steps: previous_run.steps.split_off(previous_run.completed),
+            overwrites: Vec::new(),
+            skipped: Vec::new(),
+            resumes: Some(usize::from(caught_up)),
+            finishes_run: true,
+        }))
+    }
+}
+
+/// Carries out a plan, recording it in the journal as it goes.
+pub fn apply(plan: &RenamePlan) -> Result<()> {
+    apply_with(plan, |_| {})
+}
+
+/// Carries out a plan, calling `on_step` after each rename. Stops at the first rename that
+/// fails, since later renames may depend on it. If that was the very first rename of a new run,
+/// its journal is removed again, since there is nothing to resume or undo.
+pub fn apply_with<F: FnMut(&RenameOp)>(plan: &RenamePlan, mut on_step: F) -> Result<()> {
+    if plan.steps.is_empty() && !plan.finishes_run {
+        return Ok(());
+    }
+
+    // the journal has to be on disk before anything is renamed, or a crash would leave no record
+    let mut journal = match plan.resumes {
+        None => Journal::create(&plan.directory, &plan.steps)?,
+        Some(unrecorded) => {
+            let mut journal = Journal::append(&plan.directory)?;
+            for _ in 0..unrecorded {
+                journal.mark_done()?;
+            }
+            journal
+        }
+    };
+
+    for (i, step) in plan.steps.iter().enumerate() {
+        if let Err(e) = rename(step) {
+            if i == 0 && !plan.finishes_run {
+                journal.discard()?;
+            }
+            return Err(e);
+        }
+        journal.mark_done()?;
+        on_step(step);
+    }
+    Ok(())
+}
+
+fn rename(step: &RenameOp) -> Result<()> {
+    // deleted files go into a trash directory, which may not be there yet
+    if let Some(parent) = step.to.parent() {
+        if !parent.exists() {
+            fs::create_dir(parent).map_err(|e| Error::io(parent.to_path_buf(), e))?;
+        }
+    }
+    fs::rename(&step.from, &step.to).map_err(|e| Error::io(step.from.clone(), e))
+}
+
+// an interrupted run has to be dealt with first, or its files would get renumbered twice
+fn check_not_interrupted(directory: &Path) -> Result<Option<JournalContents>> {
+    match journal::read(directory)? {
+        Some(ref contents) if !contents.is_complete() => Err(Error::Interrupted(directory.to_path_buf())),
+        contents => Ok(contents),
+    }
+}
+
+// swaps the direction of every rename, and their order so that they still chain up nicely
+fn reversed(ops: Vec<RenameOp>) -> Vec<RenameOp> {
+    ops.into_iter()
+        .rev()
+        .map(|op| RenameOp { from: op.to, to: op.from })
+        .collect()
+}
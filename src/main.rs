@@ -1,39 +1,81 @@
 extern crate clap;
+extern crate father_file_numberer;
 #[macro_use]
 extern crate lazy_static;
 extern crate regex;
 
-use std::{cmp, convert::TryFrom, fs, io};
+use std::collections::{BTreeMap, HashSet};
 use std::path::Path;
 use std::process::exit;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind, SubCommand};
+use father_file_numberer::{ConflictPolicy, Error, Expression, FloorPolicy, NumberFormat, Padding, RangeOffset, RenamePlan, RenumberOptions, Renumbering, SkipReason, Template, WhichNumber, NUMBER_GROUP};
 use regex::Regex;
 
 // number of verbose flags that must be present for output to appear
 const INFO_VERBOSITY: u32 = 1;
 
+// exit codes, which are also listed at the end of --help
+const EXIT_IO_ERROR: i32 = 1;
+const EXIT_BAD_ARGUMENTS: i32 = 2;
+const EXIT_CONFLICT: i32 = 3;
+const EXIT_BAD_FILES: i32 = 4;
+const EXIT_JOURNAL: i32 = 5;
+
+const EXIT_CODES_HELP: &str = "EXIT CODES:
+    0    success
+    1    a file, directory or the journal could not be read or written
+    2    invalid arguments
+    3    files would be renamed onto each other or onto existing files, so nothing was renamed
+    4    some files could not be renumbered, so nothing was renamed
+    5    there is an interrupted run to resume first, or no journal to undo";
+
 fn main() {
     let matches = App::new("father-file-numberer")
         .version("0.1.0")
         .author("Michael Ripley <zkxs00@gmail.com>")
         .about("Renumbers files")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .after_help(EXIT_CODES_HELP)
         .arg(Arg::with_name("directory")
             .short("d")
             .long("directory")
             .takes_value(true)
             .value_name("DIRECTORY")
+            .global(true)
             .help("Sets directory to operate on. If not specified, uses the current working directory."))
         .arg(Arg::with_name("recursive")
             .short("r")
             .long("recursive")
+            .global(true)
             .help("enables directory recursion"))
+        .arg(Arg::with_name("which")
+            .long("which")
+            .takes_value(true)
+            .value_name("WHICH")
+            .validator(is_which)
+            .default_value("first")
+            .global(true)
+            .help("which number to renumber in names with more than one: first, last, or a position such as 2 for the second"))
+        .arg(Arg::with_name("include_extension")
+            .long("include-extension")
+            .global(true)
+            .help("also look for numbers in file extensions, such as the 3 in .mp3, which are left alone by default"))
+        .arg(Arg::with_name("pattern")
+            .long("pattern")
+            .takes_value(true)
+            .value_name("PATTERN")
+            .validator(is_pattern)
+            .conflicts_with_all(&["which", "include_extension"])
+            .global(true)
+            .help("regular expression with a group named n, as in (?P<n>[0-9]+), that picks out the number to renumber. Names it does not match are left alone."))
         .arg(Arg::with_name("start")
             .short("S")
             .long("start")
             .takes_value(true)
             .value_name("START")
             .validator(is_numeric)
+            .global(true)
             .help("if present, will not match files with numbers lower than this"))
         .arg(Arg::with_name("end")
             .short("E")
@@ -41,6 +83,7 @@ fn main() {
             .takes_value(true)
             .value_name("END")
             .validator(is_numeric)
+            .global(true)
             .help("if present, will not match files with numbers higher than this"))
         .arg(Arg::with_name("number_width")
             .short("w")
@@ -48,29 +91,223 @@ fn main() {
             .takes_value(true)
             .value_name("NUMBER-WIDTH")
             .validator(is_number)
-            .help("if present, will format output numbers to at least the specified width"))
+            .global(true)
+            .help("if present, will format output numbers to at least the specified width. Otherwise zero-padded numbers keep their width, so page007 becomes page008."))
+        .arg(Arg::with_name("uniform_width")
+            .long("uniform-width")
+            .conflicts_with("number_width")
+            .global(true)
+            .help("pad output numbers to the width of the widest one in their directory, so they all line up"))
+        .arg(Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .validator(is_format)
+            .global(true)
+            .help("how to write output numbers, like Rust's format strings: {n:>4} pads to 4 with spaces, {n:_<4} with underscores on the right, {n:04} with zeros, and {n:x}, {n:X}, {n:o} and {n:b} change the radix, with # as in {n:#x} adding a 0x prefix. Numbers in names are read in the same radix, along with any fill around them. A width here overrides NUMBER-WIDTH."))
         .arg(Arg::with_name("dry_run")
             .short("y")
             .long("dry-run")
+            .global(true)
             .help("do not operate, but print what would have been done"))
+        .arg(Arg::with_name("template")
+            .long("template")
+            .takes_value(true)
+            .value_name("TEMPLATE")
+            .validator(is_template)
+            .global(true)
+            .help("builds new names from a template such as \"Chapter {n} - {stem}{ext}\" instead of just swapping the number out. Placeholders are {prefix} and {suffix} around the number, {old} and {n} for the old and new numbers, which take a format as in {n:03}, {stem} and {ext} for the name and its extension, and {parent} for the directory name."))
+        .arg(Arg::with_name("floor")
+            .long("floor")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .value_name("FLOOR")
+            .validator(is_numeric)
+            .global(true)
+            .help("lowest new number allowed. Defaults to 0, or to no limit with --signed."))
+        .arg(Arg::with_name("below_floor")
+            .long("below-floor")
+            .takes_value(true)
+            .value_name("POLICY")
+            .possible_values(&["error", "clamp", "skip"])
+            .default_value("error")
+            .global(true)
+            .help("what to do when a file's new number would be below FLOOR: refuse to rename anything, use FLOOR instead, or leave the file alone"))
+        .arg(Arg::with_name("signed")
+            .long("signed")
+            .global(true)
+            .help("treat a dash right before a number as a minus sign, as in page-3, unless it follows another number as in 2019-07-25"))
+        .arg(Arg::with_name("on_conflict")
+            .long("on-conflict")
+            .takes_value(true)
+            .value_name("POLICY")
+            .possible_values(&["abort", "skip", "overwrite"])
+            .default_value("abort")
+            .global(true)
+            .help("what to do when a renamed file would land on an existing file that is not being renamed"))
         .arg(Arg::with_name("verbose")
             .short("v")
             .long("verbose")
             .multiple(true)
+            .global(true)
             .help("increase verbosity"))
         .arg(Arg::with_name("offset")
-            .required(true)
+            .required_unless("map")
             .takes_value(true)
             .allow_hyphen_values(true)
             .value_name("OFFSET")
             .validator(is_numeric)
             .help("Number (positive or negative) to offset filenames by"))
-        .get_matches();
+        .arg(Arg::with_name("scale")
+            .long("scale")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .value_name("SCALE")
+            .validator(is_numeric)
+            .help("if present, numbers are multiplied by this before OFFSET is added, for example to spread 1, 2, 3 out to 10, 20, 30"))
+        .arg(Arg::with_name("map")
+            .long("map")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .value_name("EXPRESSION")
+            .conflicts_with_all(&["offset", "scale"])
+            .validator(is_expression)
+            .help("instead of an OFFSET, gives every number n the result of an expression such as \"n < 100 ? n + 5 : n * 2\", which can use + - * / %, comparisons, && || !, ?: and parentheses"))
+        .subcommand(SubCommand::with_name("compact")
+            .about("Renumbers files consecutively in their current order, closing any gaps between them")
+            .arg(Arg::with_name("first")
+                .long("first")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("FIRST")
+                .validator(is_numeric)
+                .default_value("1")
+                .help("number to give the first file"))
+            .arg(Arg::with_name("step")
+                .long("step")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("STEP")
+                .validator(is_numeric)
+                .default_value("1")
+                .help("difference between consecutive numbers, for example 10 to leave room for inserting files later")))
+        .subcommand(SubCommand::with_name("insert")
+            .about("Makes room for new files by shifting every number from AT upwards by COUNT")
+            .arg(Arg::with_name("at")
+                .long("at")
+                .required(true)
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("AT")
+                .validator(is_numeric)
+                .help("first number to make room at"))
+            .arg(Arg::with_name("count")
+                .long("count")
+                .takes_value(true)
+                .value_name("COUNT")
+                .validator(is_number)
+                .default_value("1")
+                .help("how many numbers to make room for")))
+        .subcommand(SubCommand::with_name("delete")
+            .about("Moves the files numbered FROM to TO into a trash directory, then shifts every higher number down to close the gap")
+            .arg(Arg::with_name("from")
+                .long("from")
+                .required(true)
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("FROM")
+                .validator(is_numeric)
+                .help("first number to delete"))
+            .arg(Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("TO")
+                .validator(is_numeric)
+                .help("last number to delete. If not specified, only FROM is deleted.")))
+        .subcommand(SubCommand::with_name("move")
+            .about("Moves the files numbered FROM to TO so they come right after AFTER, shifting the files in between to make room")
+            .arg(Arg::with_name("from")
+                .long("from")
+                .required(true)
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("FROM")
+                .validator(is_numeric)
+                .help("first number of the block to move"))
+            .arg(Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("TO")
+                .validator(is_numeric)
+                .help("last number of the block to move. If not specified, only FROM is moved."))
+            .arg(Arg::with_name("after")
+                .long("after")
+                .required(true)
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("AFTER")
+                .validator(is_numeric)
+                .help("number the block should come right after")))
+        .subcommand(SubCommand::with_name("swap")
+            .about("Swaps the numbers A and B")
+            .arg(Arg::with_name("a")
+                .required(true)
+                .allow_hyphen_values(true)
+                .value_name("A")
+                .validator(is_numeric))
+            .arg(Arg::with_name("b")
+                .required(true)
+                .allow_hyphen_values(true)
+                .value_name("B")
+                .validator(is_numeric)))
+        .subcommand(SubCommand::with_name("permute")
+            .about("Renumbers files according to a list of old=new pairs, leaving every other number alone")
+            .arg(Arg::with_name("mapping")
+                .required(true)
+                .value_name("MAPPING")
+                .validator(is_mapping)
+                .help("comma separated old=new pairs, for example 3=5,5=3,9=1")))
+        .subcommand(SubCommand::with_name("shift")
+            .about("Offsets the numbers in several ranges at once, each by its own amount, leaving every other number alone")
+            .arg(Arg::with_name("rules")
+                .required(true)
+                .multiple(true)
+                .allow_hyphen_values(true)
+                .value_name("RANGE:OFFSET")
+                .validator(is_range_offset)
+                .help("a range of numbers and the amount to offset it by, for example 1-10:2 or 50-60:-5, or just 7:1 for a single number")))
+        .subcommand(SubCommand::with_name("normalize")
+            .about("Re-pads numbers without changing them, to NUMBER-WIDTH if given and otherwise to the width of the widest one in their directory")
+            .arg(Arg::with_name("strip_zeros")
+                .long("strip-zeros")
+                .help("remove leading zeros instead")))
+        .subcommand(SubCommand::with_name("reverse")
+            .about("Reverses the order of the files between START and END, or between the lowest and highest numbers found if those are not specified"))
+        .subcommand(SubCommand::with_name("undo")
+            .about("Reverts the renames made by the last run in DIRECTORY. Running it again reapplies them."))
+        .subcommand(SubCommand::with_name("resume")
+            .about("Finishes a run in DIRECTORY that was interrupted partway through")
+            .arg(Arg::with_name("rollback")
+                .long("rollback")
+                .help("revert the renames the interrupted run already made instead of finishing it")))
+        .get_matches_safe()
+        .unwrap_or_else(|e| match e.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                eprintln!("{}", e.message);
+                exit(EXIT_BAD_ARGUMENTS);
+            }
+        });
 
     // parse arguments
     let recursive = matches.is_present("recursive");
-    let start: Option<i32> = matches.value_of("start").map(|n| n.parse().unwrap());
-    let end: Option<i32> = matches.value_of("end").map(|n| n.parse().unwrap());
+    let which = parse_which(matches.value_of("which").unwrap()).unwrap();
+    let include_extension = matches.is_present("include_extension");
+    let pattern = matches.value_of("pattern").map(|pattern| regex::bytes::Regex::new(pattern).unwrap());
+    let start: Option<i64> = matches.value_of("start").map(|n| n.parse().unwrap());
+    let end: Option<i64> = matches.value_of("end").map(|n| n.parse().unwrap());
     let directory = match matches.value_of("directory") {
         /* The path library is garbage and cannot both go above the top of
          * a relative path and also respect symlinks. Oh well, this is targeted
@@ -78,112 +315,274 @@ fn main() {
          *
          * RIP symlinks
          */
-        Some(path) => Path::new(path).canonicalize().unwrap(),
-        None => Path::new(".").canonicalize().unwrap()
+        Some(path) => Path::new(path).canonicalize(),
+        None => Path::new(".").canonicalize()
+    };
+    let directory = match directory {
+        Ok(directory) => directory,
+        Err(e) => {
+            eprintln!("DIRECTORY could not be opened: {}", e);
+            exit(EXIT_BAD_ARGUMENTS);
+        }
+    };
+    let format: NumberFormat = matches.value_of("format").map_or_else(NumberFormat::default, |format| format.parse().unwrap());
+    let template: Option<Template> = matches.value_of("template").map(|template| template.parse().unwrap());
+    let normalize_matches = matches.subcommand_matches("normalize");
+    let strip_zeros = normalize_matches.is_some_and(|normalize_matches| normalize_matches.is_present("strip_zeros"));
+    // clap only sees a conflict with global arguments given after the subcommand, so check here
+    if strip_zeros && (matches.is_present("number_width") || matches.is_present("uniform_width")) {
+        eprintln!("--strip-zeros cannot be used with --number-width or --uniform-width");
+        exit(EXIT_BAD_ARGUMENTS);
+    }
+    let padding = match matches.value_of("number_width") {
+        Some(width) => Padding::Width(width.parse().unwrap()),
+        None if strip_zeros => Padding::Width(1),
+        None if matches.is_present("uniform_width") || normalize_matches.is_some() => Padding::Uniform,
+        None => Padding::Original,
     };
-    let number_width: Option<u32> = matches.value_of("number_width").map(|n| n.parse().unwrap());
     let dry_run = matches.is_present("dry_run");
+    let on_conflict = match matches.value_of("on_conflict").unwrap() {
+        "skip" => ConflictPolicy::Skip,
+        "overwrite" => ConflictPolicy::Overwrite,
+        _ => ConflictPolicy::Abort,
+    };
+    let signed = matches.is_present("signed");
+    let floor: Option<i64> = match matches.value_of("floor") {
+        Some(floor) => Some(floor.parse().unwrap()),
+        // without a sign, a negative number would not be read back as one
+        None if signed => None,
+        None => Some(0),
+    };
+    let below_floor = match matches.value_of("below_floor").unwrap() {
+        "clamp" => FloorPolicy::Clamp,
+        "skip" => FloorPolicy::Skip,
+        _ => FloorPolicy::Error,
+    };
     let verbosity = matches.occurrences_of("verbose") as u32;
-    let offset: i32 = matches.value_of("offset").unwrap().parse().unwrap();
+
+    // subcommands bring their own renumbering, which would silently replace these
+    if let (subcommand, Some(_)) = matches.subcommand() {
+        let renumbering_args = [("offset", "OFFSET"), ("scale", "--scale"), ("map", "--map")];
+        if let Some(&(_, arg)) = renumbering_args.iter().find(|&&(name, _)| matches.is_present(name)) {
+            eprintln!("{} cannot be used with the {} subcommand", arg, subcommand);
+            exit(EXIT_BAD_ARGUMENTS);
+        }
+    }
 
     // check directory
     if !directory.is_dir() {
         eprintln!("DIRECTORY is not a directory");
-        exit(1);
+        exit(EXIT_BAD_ARGUMENTS);
     }
 
     if dry_run {
         println!("This is a dry run. No files will be renamed.");
     }
 
-    // start recursion
-    let adjuster = |x: i32| x + offset;
-    process_directory(directory, recursive, start, end, dry_run, number_width, verbosity, &adjuster).unwrap();
+    if let Some(resume_matches) = matches.subcommand_matches("resume") {
+        match father_file_numberer::plan_resume(&directory, resume_matches.is_present("rollback"), on_conflict) {
+            Ok(Some(plan)) => execute(&plan, dry_run, verbosity),
+            Ok(None) => println!("no interrupted run found in DIRECTORY, nothing to resume"),
+            Err(e) => fail(&e, None),
+        }
+        return;
+    }
+
+    if matches.subcommand_matches("undo").is_some() {
+        match father_file_numberer::plan_undo(&directory, on_conflict) {
+            Ok(plan) => execute(&plan, dry_run, verbosity),
+            Err(e) => fail(&e, None),
+        }
+        return;
+    }
+
+    let renumbering = match matches.subcommand() {
+        ("compact", Some(compact_matches)) => Renumbering::Compact {
+            first: compact_matches.value_of("first").unwrap().parse().unwrap(),
+            step: compact_matches.value_of("step").unwrap().parse().unwrap(),
+        },
+        ("insert", Some(insert_matches)) => Renumbering::Insert {
+            at: insert_matches.value_of("at").unwrap().parse().unwrap(),
+            count: insert_matches.value_of("count").unwrap().parse().unwrap(),
+        },
+        ("delete", Some(delete_matches)) => {
+            let (from, to) = parse_block(delete_matches);
+            Renumbering::Delete { from, to }
+        }
+        ("move", Some(move_matches)) => {
+            let (from, to) = parse_block(move_matches);
+            let after = move_matches.value_of("after").unwrap().parse().unwrap();
+            Renumbering::Move { from, to, after }
+        }
+        ("swap", Some(swap_matches)) => {
+            let a: i64 = swap_matches.value_of("a").unwrap().parse().unwrap();
+            let b: i64 = swap_matches.value_of("b").unwrap().parse().unwrap();
+            Renumbering::Permute(vec![(a, b), (b, a)].into_iter().collect())
+        }
+        ("permute", Some(permute_matches)) => Renumbering::Permute(parse_mapping(permute_matches.value_of("mapping").unwrap()).unwrap()),
+        ("shift", Some(shift_matches)) => Renumbering::Piecewise(shift_matches.values_of("rules").unwrap()
+            .map(|rule| parse_range_offset(rule).unwrap())
+            .collect()),
+        ("normalize", Some(_)) => Renumbering::Offset(0),
+        ("reverse", Some(_)) => Renumbering::Reverse,
+        _ if matches.is_present("map") => Renumbering::Expression(matches.value_of("map").unwrap().parse().unwrap()),
+        _ => {
+            let offset = matches.value_of("offset").unwrap().parse().unwrap();
+            match matches.value_of("scale") {
+                Some(scale) => Renumbering::Affine { scale: scale.parse().unwrap(), offset },
+                None => Renumbering::Offset(offset),
+            }
+        }
+    };
+    let options = RenumberOptions {
+        renumbering,
+        recursive,
+        which,
+        include_extension,
+        signed,
+        pattern,
+        start,
+        end,
+        padding,
+        format,
+        template,
+        floor,
+        below_floor,
+        on_conflict,
+    };
+
+    // plan every rename before touching the filesystem
+    match father_file_numberer::plan(&directory, &options) {
+        Ok(plan) => execute(&plan, dry_run, verbosity),
+        Err(e) => fail(&e, if recursive { None } else { Some(&directory) }),
+    }
+
+    let (is, are) = if dry_run { ("would be", "would be") } else { ("is now", "are now") };
+    match options.renumbering.freed() {
+        Some((first, last)) if first == last => println!("number {} {} free", first, is),
+        Some((first, last)) => println!("numbers {} to {} {} free", first, last, are),
+        None => {}
+    }
 }
 
-fn process_directory<P: AsRef<Path>, F: Fn(i32) -> i32>(directory: P, recursive: bool, start: Option<i32>, end: Option<i32>, dry_run: bool, number_width: Option<u32>, verbosity: u32, adjuster: &F) -> io::Result<()> {
-    for entry in fs::read_dir(directory)? {
-        let entry = entry?;
-        let path = entry.path();
-        if recursive && path.is_dir() {
-            match process_directory(path, recursive, start, end, dry_run, number_width, verbosity, adjuster) {
-                Ok(()) => {}
-                Err(e) => return Err(e)
+// reads the FROM and TO arguments shared by the subcommands that work on a block of numbers
+fn parse_block(matches: &ArgMatches) -> (i64, i64) {
+    let from = matches.value_of("from").unwrap().parse().unwrap();
+    let to = matches.value_of("to").map_or(from, |n| n.parse().unwrap());
+    (from, to)
+}
+
+// reports on a plan, then carries it out
+fn execute(plan: &RenamePlan, dry_run: bool, verbosity: u32) {
+    let recursive = plan.steps.iter().any(|op| op.from.parent() != Some(plan.directory.as_path()));
+    let relative_to = if recursive { None } else { Some(plan.directory.as_path()) };
+
+    for skipped in &plan.skipped {
+        let path_str = display_path(&skipped.path, relative_to);
+        match skipped.reason {
+            SkipReason::Conflict(ref target) => {
+                println!("skipping {} => {}: target already exists", path_str, display_path(target, relative_to))
+            }
+            SkipReason::BelowFloor(number) => println!("skipping {}: its new number {} would be too low", path_str, number),
+            SkipReason::NonUtf8Name => println!("skipping {}: names that are not valid UTF-8 are not supported on this system", path_str),
+            _ if verbosity <= INFO_VERBOSITY => {}
+            SkipReason::NotMatching => println!("skipping non matching file {:?}", path_str),
+            SkipReason::OutOfRange => println!("skipping out of range file {:?}", path_str),
+            SkipReason::Temporary => println!("skipping temporary file {:?}", path_str),
+            SkipReason::Trash => println!("skipping trash directory {:?}", path_str),
+        }
+    }
+
+    if verbosity >= INFO_VERBOSITY {
+        for path in &plan.overwrites {
+            println!("overwriting {}", display_path(path, relative_to));
+        }
+    }
+
+    if dry_run {
+        for step in &plan.steps {
+            println!("{} => {}", display_path(&step.from, relative_to), display_path(&step.to, relative_to));
+        }
+        return;
+    }
+
+    let mut completed = 0;
+    let result = father_file_numberer::apply_with(plan, |step| {
+        println!("{} => {}", display_path(&step.from, relative_to), display_path(&step.to, relative_to));
+        completed += 1;
+    });
+    if let Err(e) = result {
+        match (&e, plan.steps.get(completed)) {
+            (Error::Io { path, source }, Some(step)) if *path == step.from => {
+                eprintln!("ERROR {} => {}: {}", display_path(&step.from, relative_to), display_path(&step.to, relative_to), source)
             }
+            _ => eprintln!("ERROR {}", e),
+        }
+        if completed == 0 {
+            eprintln!("aborting: nothing was renamed");
         } else {
-            lazy_static! {
-                static ref FILENAME: Regex = Regex::new(r#"^(.*?)([0-9]+)(.*?)$"#).unwrap();
+            eprintln!("aborting: the remaining renames were not performed. Use `resume` to try them again or `resume --rollback` to revert.");
+        }
+        exit(EXIT_IO_ERROR);
+    }
+}
+
+// explains why nothing could be renamed, then exits with the matching exit code
+fn fail(error: &Error, relative_to: Option<&Path>) -> ! {
+    let exit_code = match *error {
+        Error::InvalidRenumbering(_) => EXIT_BAD_ARGUMENTS,
+        Error::Io { .. } | Error::MalformedJournal { .. } => EXIT_IO_ERROR,
+        Error::BadFiles(ref errors) => {
+            for error in errors {
+                eprintln!("ERROR {}: {}", display_path(&error.path, relative_to), error.kind);
             }
-            let os_filename = entry.file_name(); // explicitly save this because it would get freed as a temporary
-            let filename = os_filename.to_str().unwrap();
-            match FILENAME.captures(filename) {
-                Some(captures) => {
-                    let prefix = captures.get(1).unwrap().as_str();
-                    let number: i32 = captures.get(2).unwrap().as_str().parse().unwrap();
-                    let suffix = captures.get(3).unwrap().as_str();
-
-                    // check number range
-                    let start_ok = start.map_or(true, |s| number >= s);
-                    let end_ok = end.map_or(true, |e| number <= e);
-                    let in_range = start_ok && end_ok;
-
-                    if in_range {
-                        let adjusted_number = adjuster(number);
-                        let pad: usize = match number_width {
-                            Some(width) => {
-                                let needed_zeros: i32 = width as i32 - log10(u32::try_from(adjusted_number).unwrap()) as i32;
-                                // make sure this isn't negative
-                                usize::try_from(cmp::max(0, needed_zeros)).unwrap()
-                            }
-                            None => 0
-                        };
-                        let new_filename = format!("{}{}{}{}", prefix, "0".repeat(pad), adjusted_number, suffix);
-
-                        let mut new_path = path.parent().unwrap().to_path_buf();
-                        new_path.push(format!("{}", new_filename));
-                        let path_str;
-                        let new_path_str;
-                        if recursive {
-                            path_str = path.display().to_string();
-                            new_path_str = new_path.display().to_string();
-                        } else {
-                            path_str = path.file_name().unwrap().to_string_lossy().into_owned();
-                            new_path_str = new_path.file_name().unwrap().to_string_lossy().into_owned();
-                        }
-
-                        if dry_run {
-                            println!("{} => {}", path_str, new_path_str)
-                        } else {
-                            match fs::rename(path.clone(), new_path.clone()) {
-                                Ok(()) => println!("{} => {}", path_str, new_path_str),
-                                Err(e) => eprintln!("ERROR {} => {}: {:?}", path_str, new_path_str, e)
-                            }
-                        }
-                    } else {
-                        if verbosity > INFO_VERBOSITY {
-                            println!("skipping out of range file {:?}", filename)
-                        }
-                    }
-                }
-                None => {
-                    if verbosity > INFO_VERBOSITY {
-                        println!("skipping non matching file {:?}", filename)
-                    }
-                }
+            EXIT_BAD_FILES
+        }
+        Error::Collisions(ref collisions) => {
+            for collision in collisions {
+                let sources: Vec<String> = collision.sources.iter().map(|path| display_path(path, relative_to)).collect();
+                eprintln!("COLLISION {} => {}", sources.join(", "), display_path(&collision.target, relative_to));
             }
+            EXIT_CONFLICT
+        }
+        Error::Conflicts(ref conflicts) => {
+            for op in conflicts {
+                eprintln!("CONFLICT {} => {}: target already exists",
+                          display_path(&op.from, relative_to), display_path(&op.to, relative_to));
+            }
+            EXIT_CONFLICT
+        }
+        Error::Interrupted(_) => {
+            eprintln!("DIRECTORY contains an interrupted run. Use `resume` to finish it or `resume --rollback` to revert it.");
+            exit(EXIT_JOURNAL);
+        }
+        Error::NoJournal(_) => {
+            eprintln!("no journal found in DIRECTORY, nothing to undo");
+            exit(EXIT_JOURNAL);
         }
+    };
+    eprintln!("aborting: {}, nothing was renamed", error);
+    exit(exit_code);
+}
+
+// shows a path relative to the directory being renumbered, or in full if there is none
+fn display_path(path: &Path, relative_to: Option<&Path>) -> String {
+    match relative_to.and_then(|directory| path.strip_prefix(directory).ok()) {
+        Some(relative_path) => relative_path.display().to_string(),
+        None => path.display().to_string(),
     }
-    Ok(())
 }
 
 fn is_numeric(v: String) -> Result<(), String> {
     lazy_static! {
         static ref NUMERIC: Regex = Regex::new(r#"^[\+\-]?[0-9]+$"#).unwrap();
     }
-    if NUMERIC.is_match(&v) {
-        Ok(())
-    } else {
+    if !NUMERIC.is_match(&v) {
         Err(String::from("The value is not numeric"))
+    } else if v.parse::<i64>().is_err() {
+        Err(String::from("The value is too large"))
+    } else {
+        Ok(())
     }
 }
 
@@ -191,37 +590,96 @@ fn is_number(v: String) -> Result<(), String> {
     lazy_static! {
         static ref NUMBER: Regex = Regex::new(r#"^[1-9][0-9]*$"#).unwrap();
     }
-    if NUMBER.is_match(&v) {
-        Ok(())
-    } else {
+    if !NUMBER.is_match(&v) {
         Err(String::from("The value is not numeric"))
+    } else if v.parse::<u32>().is_err() {
+        Err(String::from("The value is too large"))
+    } else {
+        Ok(())
     }
 }
 
-fn log2(n: u32) -> u32 {
-    if n != 0 {
-        32 - n.leading_zeros()
-    } else {
-        0
-    }
-}
-
-fn log10(n: u32) -> u8 {
-    static GUESS: [u8; 33] = [
-        0, 0, 0, 0, 1, 1, 1, 2, 2, 2,
-        3, 3, 3, 3, 4, 4, 4, 5, 5, 5,
-        6, 6, 6, 6, 7, 7, 7, 8, 8, 8,
-        9, 9, 9
-    ];
-    static TEN_TO_THE: [u32; 10] = [
-        1, 10, 100, 1000, 10000, 100000,
-        1000000, 10000000, 100000000, 1000000000
-    ];
-    let digits = GUESS[log2(n) as usize];
-    let adjustment = if n >= TEN_TO_THE[digits as usize] {
-        1
-    } else {
-        0
+fn is_expression(v: String) -> Result<(), String> {
+    v.parse::<Expression>().map(|_| ())
+}
+
+fn is_template(v: String) -> Result<(), String> {
+    v.parse::<Template>().map(|_| ())
+}
+
+fn is_format(v: String) -> Result<(), String> {
+    v.parse::<NumberFormat>().map(|_| ())
+}
+
+fn is_pattern(v: String) -> Result<(), String> {
+    match regex::bytes::Regex::new(&v) {
+        Ok(ref pattern) if pattern.capture_names().any(|name| name == Some(NUMBER_GROUP)) => Ok(()),
+        Ok(_) => Err(format!("The pattern has no group named {}, as in (?P<{}>[0-9]+)", NUMBER_GROUP, NUMBER_GROUP)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn is_which(v: String) -> Result<(), String> {
+    parse_which(&v).map(|_| ())
+}
+
+fn parse_which(v: &str) -> Result<WhichNumber, String> {
+    match v {
+        "first" => Ok(WhichNumber::First),
+        "last" => Ok(WhichNumber::Last),
+        _ => match v.parse() {
+            Ok(n) if n > 0 => Ok(WhichNumber::Nth(n)),
+            _ => Err(String::from("The value must be first, last or a position counting from 1")),
+        },
+    }
+}
+
+fn is_range_offset(v: String) -> Result<(), String> {
+    parse_range_offset(&v).map(|_| ())
+}
+
+fn parse_range_offset(v: &str) -> Result<RangeOffset, String> {
+    lazy_static! {
+        static ref RANGE_OFFSET: Regex = Regex::new(r#"^([\+\-]?[0-9]+)(?:-([\+\-]?[0-9]+))?:([\+\-]?[0-9]+)$"#).unwrap();
+    }
+    let captures = match RANGE_OFFSET.captures(v) {
+        Some(captures) => captures,
+        None => return Err(format!("{:?} is not a FROM-TO:OFFSET rule", v)),
     };
-    digits + adjustment
+    let from: i64 = captures[1].parse().map_err(|_| format!("{} is too large", &captures[1]))?;
+    let to: i64 = match captures.get(2) {
+        Some(to) => to.as_str().parse().map_err(|_| format!("{} is too large", to.as_str()))?,
+        None => from,
+    };
+    let offset: i64 = captures[3].parse().map_err(|_| format!("{} is too large", &captures[3]))?;
+    if to < from {
+        return Err(format!("{:?} ends before it starts", v));
+    }
+    Ok(RangeOffset { from, to, offset })
+}
+
+fn is_mapping(v: String) -> Result<(), String> {
+    parse_mapping(&v).map(|_| ())
+}
+
+fn parse_mapping(v: &str) -> Result<BTreeMap<i64, i64>, String> {
+    let mut mapping = BTreeMap::new();
+    let mut targets = HashSet::new();
+    for pair in v.split(',') {
+        let (old, new) = match pair.find('=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => return Err(format!("{:?} is not an old=new pair", pair)),
+        };
+        let (old, new): (i64, i64) = match (old.trim().parse(), new.trim().parse()) {
+            (Ok(old), Ok(new)) => (old, new),
+            _ => return Err(format!("{:?} is not an old=new pair", pair)),
+        };
+        if mapping.insert(old, new).is_some() {
+            return Err(format!("{} is mapped more than once", old));
+        }
+        if !targets.insert(new) {
+            return Err(format!("more than one number is mapped to {}", new));
+        }
+    }
+    Ok(mapping)
 }
@@ -0,0 +1,703 @@
+// Works out which files to rename and in what order, without touching any of them.
+
+use std::{cmp, fs, str};
+use std::ffi::{OsStr, OsString};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+use regex::bytes::Regex;
+
+use bytes;
+use error::{Collision, Error, FileError, FileErrorKind, Result};
+use renumbering::Outcome;
+use template::Fields;
+use {Align, FloorPolicy, NumberFormat, Padding, Radix, RenameOp, RenumberOptions, SkipReason, Skipped, WhichNumber, NUMBER_GROUP};
+
+// what was found while looking through a directory
+#[derive(Default)]
+pub struct Scan {
+    pub ops: Vec<RenameOp>,
+    pub skipped: Vec<Skipped>,
+    pub errors: Vec<FileError>,
+    // files renamed onto a file that keeps its name because its new number is the same
+    pub collisions: Vec<Collision>,
+}
+
+// the range of numbers to renumber, narrowed down to what the renumbering itself touches
+fn bounds(options: &RenumberOptions) -> (Option<i64>, Option<i64>) {
+    let (start, end) = options.renumbering.bounds();
+    let start = match (options.start, start) {
+        (Some(a), Some(b)) => Some(cmp::max(a, b)),
+        (a, b) => a.or(b),
+    };
+    let end = match (options.end, end) {
+        (Some(a), Some(b)) => Some(cmp::min(a, b)),
+        (a, b) => a.or(b),
+    };
+    (start, end)
+}
+
+// a file whose number is up for renumbering
+struct Candidate {
+    path: PathBuf,
+    prefix: Vec<u8>,
+    number: i64,
+    // how many digits the number was zero-padded to, or 1 if it was not padded
+    width: u32,
+    suffix: Vec<u8>,
+}
+
+// finds the files in a directory that need renaming, and the ones that get left alone
+pub fn plan_directory(directory: &Path, options: &RenumberOptions, scan: &mut Scan) -> Result<()> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let (start, end) = bounds(options);
+
+    let entries = fs::read_dir(directory).map_err(|e| Error::io(directory.to_path_buf(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| Error::io(directory.to_path_buf(), e))?;
+        let path = entry.path();
+        if path.file_name() == Some(TRASH_DIRNAME.as_ref()) {
+            scan.skipped.push(Skipped { path, reason: SkipReason::Trash });
+            continue;
+        }
+        if options.recursive && path.is_dir() {
+            plan_directory(&path, options, scan)?;
+        } else {
+            let os_filename = entry.file_name(); // explicitly save this because it would get freed as a temporary
+            let filename = match bytes::from_os_str(&os_filename) {
+                Some(filename) => filename,
+                None => {
+                    scan.skipped.push(Skipped { path, reason: SkipReason::NonUtf8Name });
+                    continue;
+                }
+            };
+            if is_temp_filename(filename) {
+                scan.skipped.push(Skipped { path, reason: SkipReason::Temporary });
+                continue;
+            }
+            match find_number(filename, options) {
+                Some((prefix, digits, suffix)) => {
+                    // the number is only ever ASCII digits, a sign and a radix prefix
+                    let digits = str::from_utf8(digits).unwrap();
+                    let number: i64 = match options.format.read(digits) {
+                        Some(number) => number,
+                        None => {
+                            scan.errors.push(FileError { path, kind: FileErrorKind::NumberTooLarge(digits.to_string()) });
+                            continue;
+                        }
+                    };
+
+                    // check number range
+                    let start_ok = start.is_none_or(|s| number >= s);
+                    let end_ok = end.is_none_or(|e| number <= e);
+                    let in_range = start_ok && end_ok;
+
+                    if in_range {
+                        candidates.push(Candidate {
+                            path,
+                            prefix: prefix.to_vec(),
+                            number,
+                            width: padded_width(options.format.split_number(digits).1),
+                            suffix: suffix.to_vec(),
+                        });
+                    } else {
+                        scan.skipped.push(Skipped { path, reason: SkipReason::OutOfRange });
+                    }
+                }
+                None => scan.skipped.push(Skipped { path, reason: SkipReason::NotMatching }),
+            }
+        }
+    }
+
+    // read_dir order is arbitrary, and some renumberings depend on the order of the files
+    candidates.sort_by(|a, b| a.number.cmp(&b.number).then_with(|| a.path.cmp(&b.path)));
+    let numbers: Vec<i64> = candidates.iter().map(|candidate| candidate.number).collect();
+    let adjusted_numbers = options.renumbering.renumber(&numbers, (start, end));
+
+    // files that get a new number, along with that number
+    let mut renumbered: Vec<(Candidate, i64)> = Vec::new();
+    let mut trashed: HashSet<PathBuf> = HashSet::new();
+    // anything other than a directory in the trash's place would make every move into it fail
+    let trash_directory = directory.join(TRASH_DIRNAME);
+    let trash_usable = fs::metadata(&trash_directory).map_or(true, |metadata| metadata.is_dir());
+    for (candidate, outcome) in candidates.into_iter().zip(adjusted_numbers) {
+        match outcome {
+            Outcome::Renumber(adjusted_number) => match options.floor {
+                Some(floor) if adjusted_number < floor => match options.below_floor {
+                    FloorPolicy::Error => {
+                        scan.errors.push(FileError { path: candidate.path, kind: FileErrorKind::BelowFloor { number: adjusted_number, floor } });
+                    }
+                    FloorPolicy::Clamp => renumbered.push((candidate, floor)),
+                    FloorPolicy::Skip => scan.skipped.push(Skipped { path: candidate.path, reason: SkipReason::BelowFloor(adjusted_number) }),
+                },
+                _ => renumbered.push((candidate, adjusted_number)),
+            },
+            Outcome::Keep => scan.skipped.push(Skipped { path: candidate.path, reason: SkipReason::OutOfRange }),
+            Outcome::Trash if !trash_usable => {
+                scan.errors.push(FileError { path: candidate.path, kind: FileErrorKind::TrashNotDirectory(trash_directory.clone()) });
+            }
+            Outcome::Trash => {
+                // nothing else moves into the trash, so these never have to wait
+                let trash = trash_path(&candidate.path, &trashed);
+                trashed.insert(trash.clone());
+                scan.ops.push(RenameOp { from: candidate.path, to: trash });
+            }
+            Outcome::Overflow => {
+                scan.errors.push(FileError { path: candidate.path, kind: FileErrorKind::Overflow(candidate.number) });
+            }
+            Outcome::Undefined => {
+                scan.errors.push(FileError { path: candidate.path, kind: FileErrorKind::NoResult(candidate.number) });
+            }
+        }
+    }
+
+    // renames within this directory, along with their numbers before and after renaming
+    let mut directory_ops: Vec<(i64, i64, RenameOp)> = Vec::new();
+    let mut unchanged: HashSet<PathBuf> = HashSet::new();
+    // the widest new number of each sequence of names that only differ in their number
+    let mut widest: HashMap<(Vec<u8>, Vec<u8>), u32> = HashMap::new();
+    for (candidate, adjusted_number) in &renumbered {
+        let width = widest.entry((candidate.prefix.clone(), candidate.suffix.clone())).or_insert(1);
+        *width = cmp::max(*width, options.format.digit_count(*adjusted_number));
+    }
+    for (candidate, adjusted_number) in renumbered {
+        let Candidate { path, prefix, number, width, suffix } = candidate;
+        let width = match options.padding {
+            Padding::Original => width,
+            Padding::Width(width) => width,
+            Padding::Uniform => widest[&(prefix.clone(), suffix.clone())],
+        };
+        let number_text = options.format.format(adjusted_number, width);
+        let new_filename = match options.template {
+            Some(ref template) => {
+                let filename = bytes::from_os_str(path.file_name().unwrap()).unwrap();
+                let stem_len = filename.len() - extension_len(filename);
+                template.render(&Fields {
+                    prefix: &prefix,
+                    suffix: &suffix,
+                    stem: &filename[..stem_len],
+                    extension: &filename[stem_len..],
+                    parent: path.parent().and_then(Path::file_name).and_then(bytes::from_os_str).unwrap_or(b""),
+                    old: number,
+                    old_text: &filename[prefix.len()..filename.len() - suffix.len()],
+                    new: adjusted_number,
+                    new_text: &number_text,
+                    width,
+                })
+            }
+            None => [&prefix[..], number_text.as_bytes(), &suffix[..]].concat(),
+        };
+        // the name was only ever split at ASCII characters, so this is as valid a name as the old one
+        let new_filename = bytes::to_os_string(new_filename).unwrap();
+        if !is_filename(&new_filename) {
+            scan.errors.push(FileError { path, kind: FileErrorKind::InvalidName(new_filename) });
+            continue;
+        }
+
+        let mut new_path = path.parent().unwrap().to_path_buf();
+        new_path.push(new_filename);
+        if new_path != path {
+            directory_ops.push((number, adjusted_number, RenameOp { from: path, to: new_path }));
+        } else {
+            unchanged.insert(path);
+        }
+    }
+
+    // order_renames only sees files that move, so catch the ones landing on a file that stays
+    for (_, _, op) in &directory_ops {
+        if !unchanged.contains(&op.to) {
+            continue;
+        }
+        match scan.collisions.iter_mut().find(|collision| collision.target == op.to) {
+            Some(collision) => collision.sources.push(op.from.clone()),
+            None => scan.collisions.push(Collision { target: op.to.clone(), sources: vec![op.to.clone(), op.from.clone()] }),
+        }
+    }
+
+    // upward moves from the top down, then downward moves from the bottom up, see order_renames
+    directory_ops.sort_by_key(|&(number, adjusted_number, _)| {
+        if adjusted_number > number {
+            (false, -i128::from(number))
+        } else {
+            (true, i128::from(number))
+        }
+    });
+    scan.ops.extend(directory_ops.into_iter().map(|(_, _, op)| op));
+    Ok(())
+}
+
+// splits a filename around the number to renumber, picked out either by the pattern or as the
+// chosen run of digits in the radix of the format. Any fill the format pads numbers with is left
+// out of the parts around the number, so that formatted names are read back the way they were
+// written.
+fn find_number<'a>(filename: &'a [u8], options: &RenumberOptions) -> Option<(&'a [u8], &'a [u8], &'a [u8])> {
+    lazy_static! {
+        static ref DIGITS: Regex = Regex::new(r#"[0-9]+"#).unwrap();
+        // without their prefix, hex numbers need a digit in them, or plenty of words would count
+        static ref LOWER_HEX_DIGITS: Regex = Regex::new(r#"0x[0-9a-f]+|[0-9a-f]*[0-9][0-9a-f]*"#).unwrap();
+        static ref UPPER_HEX_DIGITS: Regex = Regex::new(r#"0x[0-9A-F]+|[0-9A-F]*[0-9][0-9A-F]*"#).unwrap();
+        static ref OCTAL_DIGITS: Regex = Regex::new(r#"0o[0-7]+|[0-7]+"#).unwrap();
+        static ref BINARY_DIGITS: Regex = Regex::new(r#"0b[01]+|[01]+"#).unwrap();
+    }
+    let (start, end) = if let Some(ref pattern) = options.pattern {
+        let number = pattern.captures(filename)?.name(NUMBER_GROUP)?;
+        let text = str::from_utf8(number.as_bytes()).ok()?;
+        if text.starts_with('-') && !options.signed || !options.format.is_number(text) {
+            return None;
+        }
+        (number.start(), number.end())
+    } else {
+        let stem = if options.include_extension {
+            filename
+        } else {
+            &filename[..filename.len() - extension_len(filename)]
+        };
+        let digits: &Regex = match options.format.radix {
+            Radix::Decimal => &DIGITS,
+            Radix::LowerHex => &LOWER_HEX_DIGITS,
+            Radix::UpperHex => &UPPER_HEX_DIGITS,
+            Radix::Octal => &OCTAL_DIGITS,
+            Radix::Binary => &BINARY_DIGITS,
+        };
+        let digits = match options.which {
+            WhichNumber::First => digits.find(stem),
+            WhichNumber::Last => digits.find_iter(stem).last(),
+            WhichNumber::Nth(n) => digits.find_iter(stem).nth(n.checked_sub(1)?),
+        }?;
+        // a dash right after another number is a separator, as in 2019-07-25, rather than a sign
+        let prefix = &filename[..digits.start()];
+        match prefix.strip_suffix(b"-") {
+            Some(before) if options.signed && !before.last().is_some_and(u8::is_ascii_digit) => (before.len(), digits.end()),
+            _ => (digits.start(), digits.end()),
+        }
+    };
+    let (fill_start, fill_end) = fill_around(filename, start, end, &options.format);
+    Some((&filename[..fill_start], &filename[start..end], &filename[fill_end..]))
+}
+
+// widens the span of a number to take in the fill that the format pads it with, on whichever side
+// the format puts it
+fn fill_around(filename: &[u8], start: usize, end: usize, format: &NumberFormat) -> (usize, usize) {
+    let (fill, align, width) = match (format.fill, format.width) {
+        (Some((fill, align)), Some(width)) => (fill, align, width as usize),
+        _ => return (start, end),
+    };
+    let mut encoded = [0; 4];
+    let fill = fill.encode_utf8(&mut encoded).as_bytes();
+    // the number itself is ASCII, so its length is the width it takes up
+    let pad = width.saturating_sub(end - start);
+    let (most_before, most_after) = match align {
+        Align::Left => (0, pad),
+        Align::Center => (pad / 2, pad - pad / 2),
+        Align::Right => (pad, 0),
+    };
+    let mut fill_start = start;
+    for _ in 0..most_before {
+        match filename[..fill_start].strip_suffix(fill) {
+            Some(before) => fill_start = before.len(),
+            None => break,
+        }
+    }
+    let mut fill_end = end;
+    for _ in 0..most_after {
+        match filename[fill_end..].strip_prefix(fill) {
+            Some(_) => fill_end += fill.len(),
+            None => break,
+        }
+    }
+    (fill_start, fill_end)
+}
+
+// whether a new name stays a single entry in the same directory. A backslash is rejected everywhere
+// so that renaming behaves the same on every platform.
+fn is_filename(name: &OsStr) -> bool {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(component)), None) => component == name && !bytes::from_os_str(name).is_some_and(|name| name.contains(&b'\\')),
+        _ => false,
+    }
+}
+
+// the length of a filename's extension including its dot, or 0 if it has none. Extensions have
+// to contain a letter, so that the 5 in "chapter 3.5" still counts as part of the name.
+fn extension_len(filename: &[u8]) -> usize {
+    const MULTI_PART_EXTENSIONS: &[&[u8]] = &[b".tar.gz", b".tar.bz2", b".tar.xz", b".tar.zst", b".tar.lz", b".tar.z"];
+
+    let lowercase = filename.to_ascii_lowercase();
+    if let Some(extension) = MULTI_PART_EXTENSIONS.iter().find(|extension| lowercase.ends_with(extension) && lowercase.len() > extension.len()) {
+        return extension.len();
+    }
+    match filename.iter().rposition(|&b| b == b'.') {
+        // a leading dot marks a hidden file rather than an extension
+        Some(0) | None => 0,
+        Some(dot) => {
+            let extension = &filename[dot + 1..];
+            let is_extension = !extension.is_empty()
+                && extension.iter().all(u8::is_ascii_alphanumeric)
+                && extension.iter().any(u8::is_ascii_alphabetic);
+            if is_extension { filename.len() - dot } else { 0 }
+        }
+    }
+}
+
+// a number like 007 was padded to its width on purpose, while 10 is just as wide as it needs to be
+fn padded_width(digits: &str) -> u32 {
+    if digits.len() > 1 && digits.starts_with('0') {
+        digits.len() as u32
+    } else {
+        1
+    }
+}
+
+// finds the renames whose target exists and is not itself being moved out of the way
+pub fn find_conflicts(ops: &[RenameOp]) -> Vec<usize> {
+    let sources: HashSet<&Path> = ops.iter().map(|op| op.from.as_path()).collect();
+    ops.iter()
+        .enumerate()
+        .filter(|(_, op)| !sources.contains(op.to.as_path()) && fs::symlink_metadata(&op.to).is_ok())
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/* Orders renames so that no rename ever lands on a file that has yet to be moved away.
+ *
+ * Each rename can only be blocked by the one rename whose source is its target. Renames are
+ * run in the order given as soon as nothing blocks them, so callers should list upward moves
+ * in descending order followed by downward moves in ascending order; for a plain offset this
+ * then never has to wait at all. If everything left is blocked it must be a cycle, which is
+ * broken by parking one file under a temporary name until its target has been freed.
+ */
+pub fn order_renames(ops: Vec<RenameOp>) -> Result<Vec<RenameOp>> {
+    // the index of the rename that moves each path away
+    let mut by_source: HashMap<&Path, usize> = HashMap::new();
+    // the index of the rename that moves a file onto each path
+    let mut by_target: HashMap<&Path, usize> = HashMap::new();
+    let mut collisions: Vec<Collision> = Vec::new();
+    for (i, op) in ops.iter().enumerate() {
+        by_source.insert(&op.from, i);
+        if let Some(&other) = by_target.get(op.to.as_path()) {
+            match collisions.iter_mut().find(|collision| collision.target == op.to) {
+                Some(collision) => collision.sources.push(op.from.clone()),
+                None => collisions.push(Collision {
+                    target: op.to.clone(),
+                    sources: vec![ops[other].from.clone(), op.from.clone()],
+                }),
+            }
+        } else {
+            by_target.insert(&op.to, i);
+        }
+    }
+    if !collisions.is_empty() {
+        return Err(Error::Collisions(collisions));
+    }
+
+    // renames that can run right now, lowest index first
+    let mut ready: BinaryHeap<Reverse<usize>> = ops.iter()
+        .enumerate()
+        .filter(|(_, op)| !by_source.contains_key(op.to.as_path()))
+        .map(|(i, _)| Reverse(i))
+        .collect();
+    let mut done = vec![false; ops.len()];
+    // files that have been moved aside to break a cycle
+    let mut parked: HashMap<usize, PathBuf> = HashMap::new();
+    let mut temp_paths: HashSet<PathBuf> = HashSet::new();
+    let mut steps = Vec::with_capacity(ops.len());
+    let mut next_undone = 0;
+
+    loop {
+        let i = match ready.pop() {
+            Some(Reverse(i)) => i,
+            None => {
+                while next_undone < ops.len() && (done[next_undone] || parked.contains_key(&next_undone)) {
+                    next_undone += 1;
+                }
+                if next_undone == ops.len() {
+                    break;
+                }
+
+                // everything left is blocked, so park a file to free up its path
+                let i = next_undone;
+                let temp = temp_path(&ops[i].from, &temp_paths);
+                temp_paths.insert(temp.clone());
+                steps.push(RenameOp { from: ops[i].from.clone(), to: temp.clone() });
+                parked.insert(i, temp);
+                if let Some(&waiting) = by_target.get(ops[i].from.as_path()) {
+                    ready.push(Reverse(waiting));
+                }
+                continue;
+            }
+        };
+
+        if done[i] {
+            continue;
+        }
+        done[i] = true;
+        match parked.remove(&i) {
+            Some(temp) => steps.push(RenameOp { from: temp, to: ops[i].to.clone() }),
+            None => {
+                steps.push(RenameOp { from: ops[i].from.clone(), to: ops[i].to.clone() });
+                // this rename just freed up its source
+                if let Some(&waiting) = by_target.get(ops[i].from.as_path()) {
+                    ready.push(Reverse(waiting));
+                }
+            }
+        }
+    }
+
+    Ok(steps)
+}
+
+const TEMP_SUFFIX: &str = ".ffn-tmp";
+
+fn is_temp_filename(filename: &[u8]) -> bool {
+    filename.starts_with(b".") && filename.ends_with(TEMP_SUFFIX.as_bytes())
+}
+
+pub const TRASH_DIRNAME: &str = ".father-file-numberer.trash";
+
+// picks an unused name in the trash next to the given path
+fn trash_path(path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    let trash = path.with_file_name(TRASH_DIRNAME);
+    let filename = path.file_name().unwrap();
+    let mut attempt = 1;
+    loop {
+        let mut trash_filename = filename.to_os_string();
+        if attempt > 1 {
+            trash_filename.push(format!(" ({})", attempt));
+        }
+        let trashed = trash.join(trash_filename);
+        if !taken.contains(&trashed) && fs::symlink_metadata(&trashed).is_err() {
+            return trashed;
+        }
+        attempt += 1;
+    }
+}
+
+// picks an unused hidden name next to the given path to park it under
+fn temp_path(path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    let filename = path.file_name().unwrap();
+    let mut attempt = 0;
+    loop {
+        let mut temp_filename = OsString::from(".");
+        temp_filename.push(filename);
+        if attempt > 0 {
+            temp_filename.push(format!(".{}", attempt));
+        }
+        temp_filename.push(TEMP_SUFFIX);
+        let temp = path.with_file_name(temp_filename);
+        if !taken.contains(&temp) && fs::symlink_metadata(&temp).is_err() {
+            return temp;
+        }
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::str;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::{find_number, order_renames};
+    use journal::{self, Journal};
+    use {apply, plan, plan_resume, ConflictPolicy, Error, FileErrorKind, NumberFormat, RenameOp, RenumberOptions, Renumbering, Template};
+
+    // a fresh directory holding a file `f<n>` for each number, containing that number
+    fn directory_with(numbers: &[i64]) -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let directory = env::temp_dir().join(format!("ffn-test-{}-{}", process::id(), COUNT.fetch_add(1, Ordering::SeqCst)));
+        fs::create_dir(&directory).unwrap();
+        for n in numbers {
+            fs::write(directory.join(format!("f{}", n)), n.to_string()).unwrap();
+        }
+        directory
+    }
+
+    // what the file `f<n>` holds, if it exists
+    fn contents(directory: &Path, n: i64) -> Option<String> {
+        fs::read_to_string(directory.join(format!("f{}", n))).ok()
+    }
+
+    fn renumber(directory: &Path, renumbering: Renumbering) -> Vec<RenameOp> {
+        let plan = plan(directory, &RenumberOptions::new(renumbering)).unwrap();
+        apply(&plan).unwrap();
+        plan.steps
+    }
+
+    fn uses_temp_names(steps: &[RenameOp]) -> bool {
+        steps.iter().any(|step| step.to.to_string_lossy().ends_with(super::TEMP_SUFFIX))
+    }
+
+    #[test]
+    fn offset_up_needs_no_temporary_names() {
+        let directory = directory_with(&(1..=10).collect::<Vec<_>>());
+        let steps = renumber(&directory, Renumbering::Offset(1));
+        assert_eq!(steps.len(), 10);
+        assert!(!uses_temp_names(&steps));
+        assert_eq!(contents(&directory, 1), None);
+        for n in 1..=10 {
+            assert_eq!(contents(&directory, n + 1), Some(n.to_string()));
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn offset_down_needs_no_temporary_names() {
+        let directory = directory_with(&(1..=10).collect::<Vec<_>>());
+        let steps = renumber(&directory, Renumbering::Offset(-1));
+        assert_eq!(steps.len(), 10);
+        assert!(!uses_temp_names(&steps));
+        assert_eq!(contents(&directory, 10), None);
+        for n in 1..=10 {
+            assert_eq!(contents(&directory, n - 1), Some(n.to_string()));
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn swap_breaks_the_cycle() {
+        let directory = directory_with(&[1, 2]);
+        let swap: BTreeMap<i64, i64> = vec![(1, 2), (2, 1)].into_iter().collect();
+        let steps = renumber(&directory, Renumbering::Permute(swap));
+        assert!(uses_temp_names(&steps));
+        assert_eq!(contents(&directory, 1), Some(String::from("2")));
+        assert_eq!(contents(&directory, 2), Some(String::from("1")));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn permute_breaks_a_longer_cycle() {
+        let directory = directory_with(&[1, 2, 3]);
+        let rotation: BTreeMap<i64, i64> = vec![(1, 2), (2, 3), (3, 1)].into_iter().collect();
+        let steps = renumber(&directory, Renumbering::Permute(rotation));
+        assert_eq!(steps.len(), 4);
+        assert_eq!(contents(&directory, 1), Some(String::from("3")));
+        assert_eq!(contents(&directory, 2), Some(String::from("1")));
+        assert_eq!(contents(&directory, 3), Some(String::from("2")));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn renames_onto_one_name_collide() {
+        let op = |from: &str, to: &str| RenameOp { from: PathBuf::from(from), to: PathBuf::from(to) };
+        match order_renames(vec![op("a", "c"), op("b", "c"), op("d", "e")]) {
+            Err(Error::Collisions(collisions)) => {
+                assert_eq!(collisions.len(), 1);
+                assert_eq!(collisions[0].target, PathBuf::from("c"));
+                assert_eq!(collisions[0].sources, vec![PathBuf::from("a"), PathBuf::from("b")]);
+            }
+            other => panic!("expected a collision, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn templates_cannot_leave_the_directory() {
+        let directory = directory_with(&[1]);
+        for template in &["..", ".", "{ext}", "a\\{n}"] {
+            let mut options = RenumberOptions::new(Renumbering::Offset(1));
+            options.template = Some(Template::parse(template).unwrap());
+            match plan(&directory, &options) {
+                Err(Error::BadFiles(errors)) => match errors[0].kind {
+                    FileErrorKind::InvalidName(_) => {}
+                    ref kind => panic!("expected an invalid name from {:?}, got {:?}", template, kind),
+                },
+                other => panic!("expected {:?} to be rejected, got {:?}", template, other),
+            }
+        }
+        assert_eq!(contents(&directory, 1), Some(String::from("1")));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    // a journal for moving `f1` to `f2`, as left behind by a run that never recorded a step
+    fn interrupted_run(directory: &Path) {
+        Journal::create(directory, &[RenameOp { from: directory.join("f1"), to: directory.join("f2") }]).unwrap();
+    }
+
+    fn is_interrupted(directory: &Path) -> bool {
+        journal::read(directory).unwrap().is_some_and(|contents| !contents.is_complete())
+    }
+
+    #[test]
+    fn reconcile_catches_up_with_an_unrecorded_rename() {
+        let directory = directory_with(&[1]);
+        interrupted_run(&directory);
+        assert!(!journal::read(&directory).unwrap().unwrap().reconcile());
+        fs::rename(directory.join("f1"), directory.join("f2")).unwrap();
+        let mut contents = journal::read(&directory).unwrap().unwrap();
+        assert!(contents.reconcile());
+        assert!(contents.is_complete());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn resume_finishes_a_run_killed_after_its_last_rename() {
+        let directory = directory_with(&[1]);
+        interrupted_run(&directory);
+        fs::rename(directory.join("f1"), directory.join("f2")).unwrap();
+        let resume = plan_resume(&directory, false, ConflictPolicy::Abort).unwrap().unwrap();
+        assert!(resume.steps.is_empty());
+        apply(&resume).unwrap();
+        assert!(!is_interrupted(&directory));
+        assert_eq!(contents(&directory, 2), Some(String::from("1")));
+        assert!(plan_resume(&directory, false, ConflictPolicy::Abort).unwrap().is_none());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn rollback_finishes_a_run_killed_before_its_first_rename() {
+        let directory = directory_with(&[1]);
+        interrupted_run(&directory);
+        let rollback = plan_resume(&directory, true, ConflictPolicy::Abort).unwrap().unwrap();
+        assert!(rollback.steps.is_empty());
+        apply(&rollback).unwrap();
+        assert!(!is_interrupted(&directory));
+        assert_eq!(contents(&directory, 1), Some(String::from("1")));
+        renumber(&directory, Renumbering::Offset(1));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn failing_first_rename_leaves_no_interrupted_run() {
+        let directory = directory_with(&[1]);
+        let plan = plan(&directory, &RenumberOptions::new(Renumbering::Offset(1))).unwrap();
+        fs::remove_file(directory.join("f1")).unwrap();
+        assert!(apply(&plan).is_err());
+        assert!(journal::read(&directory).unwrap().is_none());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn delete_needs_a_trash_directory() {
+        let directory = directory_with(&[1, 2]);
+        fs::write(directory.join(super::TRASH_DIRNAME), "").unwrap();
+        match plan(&directory, &RenumberOptions::new(Renumbering::Delete { from: 1, to: 1 })) {
+            Err(Error::BadFiles(errors)) => match errors[0].kind {
+                FileErrorKind::TrashNotDirectory(_) => {}
+                ref kind => panic!("expected the trash to be in the way, got {:?}", kind),
+            },
+            other => panic!("expected the delete to be rejected, got {:?}", other),
+        }
+        assert!(journal::read(&directory).unwrap().is_none());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn formatted_numbers_are_found_again() {
+        for spec in &[">3", "<4", "_^6", "04", "#x", "x", "#X", "#o", "#b", "*>#8x"] {
+            let mut options = RenumberOptions::new(Renumbering::Offset(1));
+            options.format = NumberFormat::parse(spec).unwrap();
+            options.signed = true;
+            for &n in &[0, 7, 31, -5, 1234] {
+                let filename = format!("Track {}.mp3", options.format.format(n, 2));
+                let (prefix, digits, suffix) = find_number(filename.as_bytes(), &options).unwrap();
+                assert_eq!((prefix, suffix), (&b"Track "[..], &b".mp3"[..]), "{:?} split wrong with {:?}", filename, spec);
+                assert_eq!(options.format.read(str::from_utf8(digits).unwrap()), Some(n), "{:?} read wrong with {:?}", filename, spec);
+            }
+        }
+    }
+}
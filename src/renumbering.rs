@@ -0,0 +1,217 @@
+// The different ways new numbers can be worked out from old ones.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use {EvaluationError, Expression};
+
+/// How matched files get their new numbers.
+#[derive(Clone, Debug)]
+pub enum Renumbering {
+    /// Adds a fixed amount to every number.
+    Offset(i64),
+    /// Multiplies every number by `scale`, then adds `offset`.
+    Affine { scale: i64, offset: i64 },
+    /// Gives every number the result of an expression of it.
+    Expression(Expression),
+    /// Numbers the files `step` apart starting from `first`, in the order of their current
+    /// numbers, closing any gaps between them.
+    Compact { first: i64, step: i64 },
+    /// Makes room for `count` new files at `at` by shifting every number from `at` upwards.
+    Insert { at: i64, count: i64 },
+    /// Moves the files numbered `from` to `to` into the trash, then shifts every higher number
+    /// down to close the gap they left.
+    Delete { from: i64, to: i64 },
+    /// Moves the block of numbers `from` to `to` so that it comes right after `after`, shifting
+    /// the numbers in between to make room.
+    Move { from: i64, to: i64, after: i64 },
+    /// Gives each number in the map the number it maps to, leaving every other number alone.
+    Permute(BTreeMap<i64, i64>),
+    /// Adds a different amount to the numbers in each of several ranges, leaving every number
+    /// outside of them alone.
+    Piecewise(Vec<RangeOffset>),
+    /// Reverses the order of the numbers, so that the lowest one gets the highest number and so
+    /// on. The range reversed is the start and end bounds if set, otherwise the lowest and
+    /// highest numbers found.
+    Reverse,
+}
+
+/// An amount to add to the numbers `from` to `to`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RangeOffset {
+    pub from: i64,
+    pub to: i64,
+    pub offset: i64,
+}
+
+// what becomes of a single file
+pub(crate) enum Outcome {
+    Renumber(i64),
+    Trash,
+    // the file is not touched by the renumbering at all
+    Keep,
+    // the new number does not fit
+    Overflow,
+    // the renumbering has no new number for it, like an expression dividing by zero
+    Undefined,
+}
+
+impl From<Option<i64>> for Outcome {
+    fn from(number: Option<i64>) -> Outcome {
+        number.map_or(Outcome::Overflow, Outcome::Renumber)
+    }
+}
+
+impl Renumbering {
+    /// Checks that the renumbering makes sense, for example that its ranges do not overlap.
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Renumbering::Affine { scale: 0, .. } => Err(String::from("scale must not be zero")),
+            Renumbering::Compact { step: 0, .. } => Err(String::from("step must not be zero")),
+            Renumbering::Delete { from, to } | Renumbering::Move { from, to, .. } if to < from => Err(format!("block {} to {} is empty", from, to)),
+            Renumbering::Move { from, to, after } if from <= after && after < to => {
+                Err(format!("{} is inside the block {} to {} being moved", after, from, to))
+            }
+            Renumbering::Piecewise(ref ranges) => {
+                let mut ranges = ranges.clone();
+                ranges.sort_by_key(|range| range.from);
+                if let Some(range) = ranges.iter().find(|range| range.to < range.from) {
+                    return Err(format!("range {} to {} is empty", range.from, range.to));
+                }
+                match ranges.windows(2).find(|pair| pair[0].to >= pair[1].from) {
+                    Some(pair) => Err(format!("ranges {} to {} and {} to {} overlap", pair[0].from, pair[0].to, pair[1].from, pair[1].to)),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The lowest and highest numbers this renumbering touches, on top of any bounds the user set.
+    pub fn bounds(&self) -> (Option<i64>, Option<i64>) {
+        match *self {
+            Renumbering::Offset(_) | Renumbering::Affine { .. } | Renumbering::Expression(_) | Renumbering::Compact { .. } | Renumbering::Reverse => (None, None),
+            Renumbering::Insert { at, .. } => (Some(at), None),
+            Renumbering::Delete { from, .. } => (Some(from), None),
+            Renumbering::Move { from, to, after } => {
+                if after < from {
+                    (Some(after.saturating_add(1)), Some(to))
+                } else {
+                    (Some(from), Some(after))
+                }
+            }
+            Renumbering::Permute(ref map) => (map.keys().next().cloned(), map.keys().next_back().cloned()),
+            Renumbering::Piecewise(ref ranges) => (
+                ranges.iter().map(|range| range.from).min(),
+                ranges.iter().map(|range| range.to).max(),
+            ),
+        }
+    }
+
+    /// The range of numbers that no file will have once this renumbering is done, if it clears one.
+    pub fn freed(&self) -> Option<(i64, i64)> {
+        match *self {
+            Renumbering::Insert { at, count } => Some((at, at.saturating_add(count - 1))),
+            _ => None,
+        }
+    }
+
+    /* Works out what becomes of each of the given numbers, which belong to the files of a
+     * single directory and are sorted in ascending order, ties broken by name. They all lie
+     * within the given start and end bounds.
+     */
+    pub(crate) fn renumber(&self, numbers: &[i64], bounds: (Option<i64>, Option<i64>)) -> Vec<Outcome> {
+        match *self {
+            Renumbering::Offset(offset) => numbers.iter().map(|&n| n.checked_add(offset).into()).collect(),
+            Renumbering::Affine { scale, offset } => numbers.iter()
+                .map(|&n| n.checked_mul(scale).and_then(|n| n.checked_add(offset)).into())
+                .collect(),
+            Renumbering::Expression(ref expression) => numbers.iter()
+                .map(|&n| match expression.evaluate(n) {
+                    Ok(n) => Outcome::Renumber(n),
+                    Err(EvaluationError::Overflow) => Outcome::Overflow,
+                    Err(EvaluationError::DivisionByZero) => Outcome::Undefined,
+                })
+                .collect(),
+            Renumbering::Compact { first, step } => (0..numbers.len())
+                .map(|i| i64::try_from(i).ok()
+                    .and_then(|i| i.checked_mul(step))
+                    .and_then(|i| first.checked_add(i))
+                    .into())
+                .collect(),
+            Renumbering::Insert { count, .. } => numbers.iter().map(|&n| n.checked_add(count).into()).collect(),
+            Renumbering::Delete { from, to } => numbers.iter()
+                .map(|&n| if n <= to {
+                    Outcome::Trash
+                } else {
+                    to.checked_sub(from)
+                        .and_then(|gap| gap.checked_add(1))
+                        .and_then(|gap| n.checked_sub(gap))
+                        .into()
+                })
+                .collect(),
+            Renumbering::Move { from, to, after } => numbers.iter()
+                .map(|&n| move_block(n, from, to, after).into())
+                .collect(),
+            Renumbering::Permute(ref map) => numbers.iter()
+                .map(|n| map.get(n).map_or(Outcome::Keep, |&n| Outcome::Renumber(n)))
+                .collect(),
+            Renumbering::Piecewise(ref ranges) => numbers.iter()
+                .map(|&n| match ranges.iter().find(|range| range.from <= n && n <= range.to) {
+                    Some(range) => n.checked_add(range.offset).into(),
+                    None => Outcome::Keep,
+                })
+                .collect(),
+            Renumbering::Reverse => {
+                let (start, end) = bounds;
+                let low = start.or_else(|| numbers.first().cloned()).unwrap_or(0);
+                let high = end.or_else(|| numbers.last().cloned()).unwrap_or(0);
+                numbers.iter()
+                    .map(|&n| i64::try_from(i128::from(low) + i128::from(high) - i128::from(n)).ok().into())
+                    .collect()
+            }
+        }
+    }
+}
+
+// where a number ends up when the block from..=to is moved to right after `after`
+fn move_block(n: i64, from: i64, to: i64, after: i64) -> Option<i64> {
+    let length = to.checked_sub(from)?.checked_add(1)?;
+    if after < from {
+        // the block moves down, and what it jumps over moves up
+        if n >= from {
+            n.checked_sub(from)?.checked_add(after)?.checked_add(1)
+        } else {
+            n.checked_add(length)
+        }
+    } else if after > to {
+        // the block moves up, and what it jumps over moves down
+        if n <= to {
+            n.checked_add(after.checked_sub(to)?)
+        } else {
+            n.checked_sub(length)
+        }
+    } else {
+        Some(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Renumbering;
+
+    #[test]
+    fn rejects_empty_blocks() {
+        assert!(Renumbering::Delete { from: 3, to: 3 }.validate().is_ok());
+        assert!(Renumbering::Delete { from: 5, to: 3 }.validate().is_err());
+        assert!(Renumbering::Move { from: 10, to: 5, after: 20 }.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_moves_into_the_block() {
+        assert!(Renumbering::Move { from: 5, to: 8, after: 2 }.validate().is_ok());
+        assert!(Renumbering::Move { from: 5, to: 8, after: 8 }.validate().is_ok());
+        assert!(Renumbering::Move { from: 5, to: 8, after: 5 }.validate().is_err());
+        assert!(Renumbering::Move { from: 5, to: 8, after: 7 }.validate().is_err());
+    }
+}
@@ -0,0 +1,178 @@
+// Templates for new filenames, such as `{prefix}{n:03}{suffix}` or `Chapter {n} - {stem}{ext}`.
+
+use std::str::FromStr;
+
+use NumberFormat;
+
+/// A pattern that new filenames are built from.
+///
+/// Placeholders are `{prefix}` and `{suffix}` for the parts of the name around the number,
+/// `{old}` and `{n}` for the old and new numbers, `{stem}` and `{ext}` for the name split at its
+/// extension, and `{parent}` for the name of the directory the file is in. The numbers take a
+/// format spec as in `{n:>4}`. Braces are written as `{{` and `}}`.
+#[derive(Clone, Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Prefix,
+    Suffix,
+    Stem,
+    Extension,
+    Parent,
+    Old(Option<NumberFormat>),
+    New(Option<NumberFormat>),
+}
+
+// everything a template can refer to for a single file
+pub(crate) struct Fields<'a> {
+    pub prefix: &'a [u8],
+    pub suffix: &'a [u8],
+    pub stem: &'a [u8],
+    pub extension: &'a [u8],
+    pub parent: &'a [u8],
+    pub old: i64,
+    // the old number as it was written in the name
+    pub old_text: &'a [u8],
+    pub new: i64,
+    // the new number as it would be written without a template
+    pub new_text: &'a str,
+    // the width the new number is padded to without a format of its own
+    pub width: u32,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| String::from("unclosed {"))?;
+                    let placeholder = &rest[..end];
+                    for _ in 0..=placeholder.chars().count() {
+                        chars.next();
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(literal.split_off(0)));
+                    }
+                    parts.push(Part::parse(placeholder)?);
+                }
+                '}' => return Err(String::from("unmatched }, which has to be written as }}")),
+                '/' => return Err(String::from("templates cannot contain /")),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    pub(crate) fn render(&self, fields: &Fields) -> Vec<u8> {
+        let mut filename = Vec::new();
+        for part in &self.parts {
+            match *part {
+                Part::Literal(ref literal) => filename.extend_from_slice(literal.as_bytes()),
+                Part::Prefix => filename.extend_from_slice(fields.prefix),
+                Part::Suffix => filename.extend_from_slice(fields.suffix),
+                Part::Stem => filename.extend_from_slice(fields.stem),
+                Part::Extension => filename.extend_from_slice(fields.extension),
+                Part::Parent => filename.extend_from_slice(fields.parent),
+                Part::Old(None) => filename.extend_from_slice(fields.old_text),
+                Part::Old(Some(ref format)) => filename.extend_from_slice(format.format(fields.old, 0).as_bytes()),
+                Part::New(None) => filename.extend_from_slice(fields.new_text.as_bytes()),
+                Part::New(Some(ref format)) => filename.extend_from_slice(format.format(fields.new, fields.width).as_bytes()),
+            }
+        }
+        filename
+    }
+}
+
+impl Part {
+    fn parse(placeholder: &str) -> Result<Part, String> {
+        let (name, spec) = match placeholder.find(':') {
+            Some(colon) => (&placeholder[..colon], Some(&placeholder[colon + 1..])),
+            None => (placeholder, None),
+        };
+        let part = match name {
+            "prefix" => Part::Prefix,
+            "suffix" => Part::Suffix,
+            "stem" => Part::Stem,
+            "ext" => Part::Extension,
+            "parent" => Part::Parent,
+            "old" => return Ok(Part::Old(spec.map(NumberFormat::parse).transpose()?)),
+            "n" => return Ok(Part::New(spec.map(NumberFormat::parse).transpose()?)),
+            _ => return Err(format!("unknown placeholder {{{}}}", name)),
+        };
+        match spec {
+            Some(_) => Err(format!("{{{}}} does not take a format", name)),
+            None => Ok(part),
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Template, String> {
+        Template::parse(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fields, Template};
+
+    fn render(source: &str) -> String {
+        let fields = Fields {
+            prefix: b"ch ",
+            suffix: b".txt",
+            stem: b"ch 07",
+            extension: b".txt",
+            parent: b"book",
+            old: 7,
+            old_text: b"07",
+            new: 12,
+            new_text: "012",
+            width: 3,
+        };
+        String::from_utf8(Template::parse(source).unwrap().render(&fields)).unwrap()
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(render("{prefix}{n}{suffix}"), "ch 012.txt");
+        assert_eq!(render("{parent} {old} to {n}{ext}"), "book 07 to 012.txt");
+        assert_eq!(render("{stem} - {n:>4}"), "ch 07 -   12");
+        assert_eq!(render("{old:x}-{n:#b}"), "7-0b1100");
+        assert_eq!(render("{n:}"), "012");
+        assert_eq!(render("plain"), "plain");
+    }
+
+    #[test]
+    fn renders_escaped_braces() {
+        assert_eq!(render("{{{n}}}"), "{012}");
+        assert_eq!(render("{{n}}"), "{n}");
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for source in &["{nope}", "{n", "n}", "{n}}x", "a/{n}", "{prefix:04}", "{n:q}", "{n:/>4}"] {
+            assert!(Template::parse(source).is_err(), "{:?} was accepted", source);
+        }
+    }
+}